@@ -1,12 +1,16 @@
+use std::time::Duration;
+
 use crate::{
     config::{DataSource, DataSourceType},
     parsers::QueryInput,
-    query::{QueryError, QueryExecutionError, QueryResult},
+    query::{QueryError, QueryExecutionError, QueryOutput, QueryPlan, QueryResult, SchemaColumn},
 };
 
 use aws_athena_adapter::AwsAthenaAdapter;
 use new_relic_log_adapter::NewRelicLogAdapter;
 
+pub use new_relic_log_adapter::MAX_LIMIT as NEW_RELIC_MAX_LIMIT;
+
 use async_trait::async_trait;
 
 mod aws_athena_adapter;
@@ -14,16 +18,46 @@ mod new_relic_log_adapter;
 
 #[async_trait]
 pub trait QueryAdapter<'a> {
-    fn build_query(&self, input: &'a QueryInput) -> Result<String, QueryError>;
+    async fn build_query(&self, input: &'a QueryInput) -> Result<String, QueryError>;
+
+    async fn execute_query(&self, query: &str) -> Result<QueryOutput, QueryExecutionError>;
 
-    async fn execute_query(&self, query: &str) -> Result<QueryResult, QueryExecutionError>;
+    /// Applies the parts of `input` the query language of the data source can't
+    /// express to the rows of the query
+    fn post_process(&self, _input: &'a QueryInput, _result: &mut QueryResult) {}
+
+    /// Explains `query` instead of running it, `analyze` runs it to measure each step
+    async fn explain(&self, query: &str, analyze: bool) -> Result<QueryPlan, QueryExecutionError>;
+
+    /// Columns of the queried table, `refresh` bypasses any cache
+    async fn schema(&self, refresh: bool) -> Result<Vec<SchemaColumn>, QueryExecutionError>;
 }
 
-pub struct AdapterFactory;
+/// Options applying to the queries of every data source
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ExecutionOptions {
+    /// Rows read per data source at most
+    pub max_rows: Option<usize>,
+    /// Overrides the timeout of every data source
+    pub timeout: Option<Duration>,
+    /// Only read what is cached, e.g. table schemas, so building queries sends no requests
+    pub offline: bool,
+}
+
+pub struct AdapterFactory {
+    options: ExecutionOptions,
+}
 
 impl AdapterFactory {
     pub fn new() -> Self {
-        Self {}
+        Self {
+            options: ExecutionOptions::default(),
+        }
+    }
+
+    pub fn options(mut self, options: ExecutionOptions) -> Self {
+        self.options = options;
+        self
     }
 
     pub fn create_adapter<'a>(
@@ -31,8 +65,12 @@ impl AdapterFactory {
         data_source: &'a DataSource,
     ) -> Box<dyn QueryAdapter<'a> + 'a> {
         match data_source.source_type {
-            DataSourceType::AwsAthenaALBLog => Box::new(AwsAthenaAdapter::new(&data_source)),
-            DataSourceType::NewRelicLog => Box::new(NewRelicLogAdapter::new(&data_source)),
+            DataSourceType::AwsAthenaALBLog => {
+                Box::new(AwsAthenaAdapter::new(data_source, self.options))
+            }
+            DataSourceType::NewRelicLog => {
+                Box::new(NewRelicLogAdapter::new(data_source, self.options))
+            }
         }
     }
 }
@@ -4,27 +4,79 @@ mod query_executor;
 mod query_result_fetcher;
 mod query_result_processor;
 mod query_status_checker;
+mod schema;
 
-use crate::adapters::aws_athena_adapter::{client::Client, query_builder::QueryBuilder};
+use crate::adapters::aws_athena_adapter::{
+    client::{Client, DEFAULT_TIMEOUT},
+    query_builder::{AthenaAlbColumn, QueryBuilder},
+    schema::Schema,
+};
 
 use crate::config::{AwsAthenaALBLog, DataSourceDetails};
 use crate::parsers::QueryInput;
 use crate::{
-    adapters::QueryAdapter,
+    adapters::{ExecutionOptions, QueryAdapter},
     config::DataSource,
-    query::{QueryError, QueryExecutionError, QueryResult},
+    query::{QueryError, QueryExecutionError, QueryOutput, QueryPlan, SchemaColumn},
 };
 
+use std::time::Duration;
+
 use async_trait::async_trait;
+use log::warn;
+use regex::Regex;
+use serde_json::Value;
 
 pub struct AwsAthenaAdapter<'a> {
+    id: &'a str,
     details: &'a AwsAthenaALBLog,
+    options: ExecutionOptions,
 }
 
 impl<'a> AwsAthenaAdapter<'a> {
-    pub fn new(data_source: &'a DataSource) -> Self {
+    /// Columns of the table from its (cached) schema, or the documented ALB columns
+    /// if the schema can't be read
+    async fn columns(&self) -> Vec<AthenaAlbColumn> {
+        let schema = if self.options.offline {
+            Schema::cached(self.id, self.details).ok_or(String::from("No cached schema"))
+        } else {
+            Schema::get(self.id, self.details, false)
+                .await
+                .map_err(|e| e.to_string())
+        };
+
+        match schema {
+            Ok(schema) => schema
+                .columns
+                .iter()
+                .map(AthenaAlbColumn::from_schema)
+                .collect(),
+            Err(e) => {
+                warn!(
+                    "[{}] Failed to read the table schema, using the default ALB columns: {}",
+                    self.id, e
+                );
+
+                AthenaAlbColumn::defaults(&self.details.partitions)
+            }
+        }
+    }
+
+    /// --timeout, the timeout of the data source or the default, in that order
+    fn timeout(&self) -> Duration {
+        self.options
+            .timeout
+            .or(self.details.timeout_secs.map(Duration::from_secs))
+            .unwrap_or(DEFAULT_TIMEOUT)
+    }
+
+    pub fn new(data_source: &'a DataSource, options: ExecutionOptions) -> Self {
         match &data_source.details {
-            DataSourceDetails::AwsAthenaALBLog(details) => Self { details },
+            DataSourceDetails::AwsAthenaALBLog(details) => Self {
+                id: &data_source.id,
+                details,
+                options,
+            },
             _ => panic!("AwsAthenaAdapter requires an AwsAthenaALBLog data source"),
         }
     }
@@ -32,28 +84,100 @@ impl<'a> AwsAthenaAdapter<'a> {
 
 #[async_trait]
 impl<'a> QueryAdapter<'a> for AwsAthenaAdapter<'a> {
-    async fn execute_query(&self, query: &str) -> Result<QueryResult, QueryExecutionError> {
+    async fn execute_query(&self, query: &str) -> Result<QueryOutput, QueryExecutionError> {
         let client = Client::new(&self.details.region).await;
 
         let client = client
             .catalog(&self.details.catalog)
             .database(&self.details.database)
-            .workgroup(&self.details.workgroup);
+            .workgroup(&self.details.workgroup)
+            .data_source_id(self.id)
+            .timeout(self.timeout())
+            .max_rows(self.options.max_rows);
 
-        client.execute_query(query).await
+        let output = client.execute_query(query).await?;
+
+        if output.truncated {
+            warn!(
+                "[{}] Stopped reading after {} rows, raise --max-rows for more",
+                self.id,
+                output.rows.len()
+            );
+        }
+
+        Ok(output)
     }
 
-    fn build_query(&self, input: &'a QueryInput) -> Result<String, QueryError> {
-        let mut query = QueryBuilder::new(self.details.table.as_str());
+    async fn build_query(&self, input: &'a QueryInput) -> Result<String, QueryError> {
+        let columns = self.columns().await;
+
+        let mut query = QueryBuilder::new(self.details.table.as_str(), &columns);
 
         let query_string = query
+            .window(input.since, input.until)?
             .select(&input.select)?
             .conditions(&input.conditions)?
             .facet(&input.facet)?
+            .having(&input.having)?
+            .timeseries(input.timeseries)?
+            .order_by(&input.order_by)?
+            .limit(input.limit)?
+            .partitions(&self.details.partitions, input.since, input.until)?
             .since(input.since)?
             .until(input.until)?
             .build_query();
 
         Ok(query_string)
     }
+
+    /// The plan is read from the `Query Plan` column, one row per line
+    async fn explain(&self, query: &str, analyze: bool) -> Result<QueryPlan, QueryExecutionError> {
+        let statement = if analyze {
+            format!("EXPLAIN ANALYZE {}", query)
+        } else {
+            format!("EXPLAIN {}", query)
+        };
+
+        let output = self.execute_query(&statement).await?;
+
+        let plan = output
+            .rows
+            .iter()
+            .filter_map(|row| row.get("Query Plan").and_then(Value::as_str))
+            .collect::<Vec<&str>>()
+            .join("\n");
+
+        Ok(QueryPlan {
+            estimated_scan: (!analyze).then(|| estimated_scan(&plan)).flatten(),
+            plan,
+            statistics: output.statistics.filter(|_| analyze),
+        })
+    }
+
+    async fn schema(&self, refresh: bool) -> Result<Vec<SchemaColumn>, QueryExecutionError> {
+        Ok(Schema::get(self.id, self.details, refresh).await?.columns)
+    }
+}
+
+/// Sizes the planner estimates for the table scans, e.g. `Estimates: {rows: 1000 (97.66kB), ...}`
+/// below a `TableScan` or `ScanFilter` step. Athena often has no estimate (`?`).
+fn estimated_scan(plan: &str) -> Option<String> {
+    let estimate = Regex::new(r"Estimates: \{rows: \S+ \(([^)]+)\)").unwrap();
+
+    let mut in_scan = false;
+    let mut sizes = vec![];
+
+    for line in plan.lines() {
+        if line.contains("TableScan") || line.contains("ScanFilter") || line.contains("ScanProject")
+        {
+            in_scan = true;
+        } else if in_scan {
+            if let Some(captures) = estimate.captures(line) {
+                sizes.push(captures[1].to_string());
+                in_scan = false;
+            }
+        }
+    }
+
+    (!sizes.is_empty()).then(|| sizes.join(", "))
 }
@@ -1,15 +1,24 @@
 use std::ops::Deref;
 
-use aws_config::meta::region::RegionProviderChain;
+use std::{pin::pin, time::Duration};
+
+use aws_config::{meta::region::RegionProviderChain, SdkConfig};
 use aws_sdk_athena::{
-    operation::get_query_execution::GetQueryExecutionError, Client as AthenaClient,
+    operation::get_query_execution::GetQueryExecutionError, types::ColumnInfo,
+    Client as AthenaClient,
 };
+use futures::TryStreamExt;
 
-use crate::query::{QueryExecutionError, QueryResult};
+use crate::{
+    query::{QueryExecutionError, QueryOutput, QueryResult, QueryStatistics},
+    running_queries::{RunningQuery, RUNNING_QUERIES},
+};
 
 use super::{
-    query_executor::QueryExecutor, query_result_fetcher::QueryResultFetcher,
-    query_result_processor::QueryResultProcessor, query_status_checker::QueryStatusChecker,
+    query_executor::QueryExecutor,
+    query_result_fetcher::{QueryResultFetcher, PAGE_SIZE},
+    query_result_processor::QueryResultProcessor,
+    query_status_checker::QueryStatusChecker,
 };
 
 // Allow AWS SDK errors to be converted into QueryExecutionErrors
@@ -31,8 +40,18 @@ impl
     }
 }
 
+/// How long queries may run unless the data source or --timeout say otherwise
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Result pages read with GetQueryResults before the result CSV is downloaded instead
+const MAX_PAGES: usize = 100;
+
 pub struct Client<'a> {
     pub client: AthenaClient,
+    pub config: SdkConfig,
+    pub max_rows: Option<usize>,
+    pub timeout: Duration,
+    pub data_source_id: &'a str,
     pub catalog: &'a str,
     pub workgroup: &'a str,
     pub database: &'a str,
@@ -58,6 +77,10 @@ impl<'a> Client<'a> {
 
         Self {
             client,
+            config: shared_config,
+            max_rows: None,
+            timeout: DEFAULT_TIMEOUT,
+            data_source_id: "athena",
             catalog: "AwsDataCatalog",
             workgroup: "primary",
             database: "default",
@@ -79,21 +102,95 @@ impl<'a> Client<'a> {
         self
     }
 
-    pub async fn execute_query(&self, query: &str) -> Result<QueryResult, QueryExecutionError> {
-        let executor = QueryExecutor::new(&self);
+    /// Labels the progress of the queries
+    pub fn data_source_id(mut self, data_source_id: &'a str) -> Self {
+        self.data_source_id = data_source_id;
+        self
+    }
+
+    /// Stops queries that run longer than `timeout`
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Stops reading the result after `max_rows` rows
+    pub fn max_rows(mut self, max_rows: Option<usize>) -> Self {
+        self.max_rows = max_rows;
+        self
+    }
+
+    pub async fn execute_query(&self, query: &str) -> Result<QueryOutput, QueryExecutionError> {
+        let executor = QueryExecutor::new(self);
         let query_execution_id = executor.start_query_execution(query).await?;
 
-        let status_checker = QueryStatusChecker::new(&self);
-        status_checker
-            .poll_query_status(&query_execution_id)
-            .await?;
+        let running = RUNNING_QUERIES.register(RunningQuery::Athena {
+            client: self.client.clone(),
+            query_execution_id: query_execution_id.clone(),
+        });
+
+        let status_checker = QueryStatusChecker::new(self);
+        let status = status_checker.poll_query_status(&query_execution_id).await;
 
-        let result_fetcher = QueryResultFetcher::new(&self);
-        let result = result_fetcher
-            .get_query_results(&query_execution_id)
-            .await?;
+        // A query nobody waits for anymore would keep running and billing
+        if let Err(QueryExecutionError::QueryTimeout(_)) = status {
+            running.stop().await;
+        } else {
+            drop(running);
+        }
+
+        let statistics = status?.map(|statistics| QueryStatistics {
+            data_scanned_bytes: statistics.data_scanned_in_bytes,
+            engine_time_ms: statistics.engine_execution_time_in_millis,
+            queue_time_ms: statistics.query_queue_time_in_millis,
+        });
 
+        let result_fetcher = QueryResultFetcher::new(self);
         let processor = QueryResultProcessor::new();
-        Ok(processor.process(result))
+
+        let max_rows = self.max_rows.unwrap_or(usize::MAX);
+
+        let mut columns: Vec<ColumnInfo> = Vec::new();
+        let mut rows: QueryResult = Vec::new();
+
+        // Reading stops one row past `max_rows`, and the first page starts with the
+        // header row, so only larger limits can run out of pages
+        let csv_fallback = max_rows.saturating_add(2) > MAX_PAGES * PAGE_SIZE;
+
+        let mut pages = pin!(result_fetcher.pages(&query_execution_id));
+        let mut page_count = 0;
+
+        while let Some((result_set, more_pages)) = pages.try_next().await? {
+            page_count += 1;
+
+            if let Some(column_info) = result_set
+                .result_set_metadata
+                .and_then(|metadata| metadata.column_info)
+            {
+                columns = column_info;
+            }
+
+            rows.extend(processor.process(&columns, result_set.rows.unwrap_or_default()));
+
+            if rows.len() > max_rows {
+                break;
+            }
+
+            // The CSV holds the complete result, so the rows read so far are replaced
+            if csv_fallback && more_pages && page_count == MAX_PAGES {
+                let csv = result_fetcher.download_csv(&query_execution_id).await?;
+                rows = processor.process_csv(&columns, &csv);
+                break;
+            }
+        }
+
+        let truncated = rows.len() > max_rows;
+        rows.truncate(max_rows);
+
+        Ok(QueryOutput {
+            rows,
+            truncated,
+            statistics,
+        })
     }
 }
@@ -1,220 +1,670 @@
-use std::str::FromStr;
+use std::{borrow::Cow, ops::RangeInclusive};
 
-use chrono::NaiveDateTime;
+use chrono::{DateTime, FixedOffset, NaiveDate, TimeDelta, Utc};
 
 use crate::{
-    parsers::{Facet, Select, Where},
-    query::QueryError,
+    config::Partition,
+    error::did_you_mean,
+    parsers::{Condition, DateTimeParser, Facet, OrderBy, Parser, Select, StatusCodeParser, Where},
+    query::{QueryError, SchemaColumn},
+    timeseries::BUCKET_COLUMN,
 };
 
-const DAY_FORMAT: &str = "%Y/%m/%d";
+/// Upper bound of OR-ed date partition predicates, beyond it the partitions are
+/// pruned more coarsely
+const MAX_PARTITION_GROUPS: usize = 31;
 const TIME_FORMAT: &str = "%Y-%m-%d-%H:%M:%S";
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum ColumnType {
     String,
     Integer,
+    Double,
+    /// ISO 8601 timestamp stored as a string
     DateTime,
 }
 
-const ATHENA_ALB_COLUMNS: [AthenaAlbColumn; 9] = [
+/// Columns of the ALB access log table as documented by AWS, plus the `day` partition
+const ATHENA_ALB_COLUMNS: [AthenaAlbColumn; 35] = [
+    // Partition of the log files, `YYYY/MM/DD`
     AthenaAlbColumn {
-        name: "day",
+        name: Cow::Borrowed("day"),
         col_type: ColumnType::String,
     },
     AthenaAlbColumn {
-        name: "time",
+        name: Cow::Borrowed("type"),
+        col_type: ColumnType::String,
+    },
+    AthenaAlbColumn {
+        name: Cow::Borrowed("time"),
         col_type: ColumnType::DateTime,
     },
     AthenaAlbColumn {
-        name: "domain_name",
+        name: Cow::Borrowed("elb"),
+        col_type: ColumnType::String,
+    },
+    AthenaAlbColumn {
+        name: Cow::Borrowed("client_ip"),
         col_type: ColumnType::String,
     },
     AthenaAlbColumn {
-        name: "elb_status_code",
+        name: Cow::Borrowed("client_port"),
         col_type: ColumnType::Integer,
     },
     AthenaAlbColumn {
-        name: "target_status_code",
+        name: Cow::Borrowed("target_ip"),
+        col_type: ColumnType::String,
+    },
+    AthenaAlbColumn {
+        name: Cow::Borrowed("target_port"),
         col_type: ColumnType::Integer,
     },
+    // Seconds, -1 if the request could not be dispatched
+    AthenaAlbColumn {
+        name: Cow::Borrowed("request_processing_time"),
+        col_type: ColumnType::Double,
+    },
+    AthenaAlbColumn {
+        name: Cow::Borrowed("target_processing_time"),
+        col_type: ColumnType::Double,
+    },
+    AthenaAlbColumn {
+        name: Cow::Borrowed("response_processing_time"),
+        col_type: ColumnType::Double,
+    },
     AthenaAlbColumn {
-        name: "request_url",
+        name: Cow::Borrowed("elb_status_code"),
+        col_type: ColumnType::Integer,
+    },
+    AthenaAlbColumn {
+        name: Cow::Borrowed("target_status_code"),
+        col_type: ColumnType::Integer,
+    },
+    AthenaAlbColumn {
+        name: Cow::Borrowed("received_bytes"),
+        col_type: ColumnType::Integer,
+    },
+    AthenaAlbColumn {
+        name: Cow::Borrowed("sent_bytes"),
+        col_type: ColumnType::Integer,
+    },
+    AthenaAlbColumn {
+        name: Cow::Borrowed("request_method"),
         col_type: ColumnType::String,
     },
     AthenaAlbColumn {
-        name: "request_method",
+        name: Cow::Borrowed("request_url"),
         col_type: ColumnType::String,
     },
     AthenaAlbColumn {
-        name: "client_ip",
+        name: Cow::Borrowed("request_proto"),
         col_type: ColumnType::String,
     },
     AthenaAlbColumn {
-        name: "target_ip",
+        name: Cow::Borrowed("user_agent"),
+        col_type: ColumnType::String,
+    },
+    AthenaAlbColumn {
+        name: Cow::Borrowed("ssl_cipher"),
+        col_type: ColumnType::String,
+    },
+    AthenaAlbColumn {
+        name: Cow::Borrowed("ssl_protocol"),
+        col_type: ColumnType::String,
+    },
+    AthenaAlbColumn {
+        name: Cow::Borrowed("target_group_arn"),
+        col_type: ColumnType::String,
+    },
+    AthenaAlbColumn {
+        name: Cow::Borrowed("trace_id"),
+        col_type: ColumnType::String,
+    },
+    AthenaAlbColumn {
+        name: Cow::Borrowed("domain_name"),
+        col_type: ColumnType::String,
+    },
+    AthenaAlbColumn {
+        name: Cow::Borrowed("chosen_cert_arn"),
+        col_type: ColumnType::String,
+    },
+    AthenaAlbColumn {
+        name: Cow::Borrowed("matched_rule_priority"),
+        col_type: ColumnType::String,
+    },
+    AthenaAlbColumn {
+        name: Cow::Borrowed("request_creation_time"),
+        col_type: ColumnType::DateTime,
+    },
+    AthenaAlbColumn {
+        name: Cow::Borrowed("actions_executed"),
+        col_type: ColumnType::String,
+    },
+    AthenaAlbColumn {
+        name: Cow::Borrowed("redirect_url"),
+        col_type: ColumnType::String,
+    },
+    AthenaAlbColumn {
+        name: Cow::Borrowed("error_reason"),
+        col_type: ColumnType::String,
+    },
+    AthenaAlbColumn {
+        name: Cow::Borrowed("target_port_list"),
+        col_type: ColumnType::String,
+    },
+    AthenaAlbColumn {
+        name: Cow::Borrowed("target_status_code_list"),
+        col_type: ColumnType::String,
+    },
+    AthenaAlbColumn {
+        name: Cow::Borrowed("classification"),
+        col_type: ColumnType::String,
+    },
+    AthenaAlbColumn {
+        name: Cow::Borrowed("classification_reason"),
+        col_type: ColumnType::String,
+    },
+    AthenaAlbColumn {
+        name: Cow::Borrowed("conn_trace_id"),
         col_type: ColumnType::String,
     },
-    // AthenaAlbColumn::TargetStatusCode,
 ];
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct AthenaAlbColumn {
-    pub name: &'static str,
+    pub name: Cow<'static, str>,
     pub col_type: ColumnType,
 }
 
 #[derive(Debug)]
 pub enum ParsedValue {
     Integer(i64),
+    Double(f64),
     String(String),
-    DateTime(NaiveDateTime),
+    DateTime(DateTime<Utc>),
+}
+
+impl ParsedValue {
+    /// SQL literal of the value. Timestamps are compared as strings in the format
+    /// of the logs, e.g. `'2024-01-01T10:00:00.000000Z'`.
+    pub fn to_literal(&self) -> String {
+        match self {
+            ParsedValue::Integer(value) => value.to_string(),
+            ParsedValue::Double(value) => value.to_string(),
+            ParsedValue::String(value) => string_literal(value),
+            ParsedValue::DateTime(value) => {
+                string_literal(&value.format("%Y-%m-%dT%H:%M:%S%.6fZ").to_string())
+            }
+        }
+    }
 }
 
 impl AthenaAlbColumn {
-    pub fn prepare_value(&self, value: &str) -> String {
-        match self.col_type {
-            ColumnType::String | ColumnType::DateTime => format!("'{}'", value),
-            ColumnType::Integer => value.to_string(),
+    pub fn prepare_value(&self, value: &str) -> Result<String, QueryError> {
+        Ok(self.parse_value(value)?.to_literal())
+    }
+
+    /// Status code class or range (e.g. `5xx`, `500-504`) given for an integer column
+    pub fn status_code_range(&self, value: &str) -> Option<RangeInclusive<u16>> {
+        if self.col_type != ColumnType::Integer || value.parse::<i64>().is_ok() {
+            return None;
         }
+
+        StatusCodeParser::from_str(value).ok()
     }
 
-    pub fn parse_value(&self, value: &str) -> Result<ParsedValue, String> {
+    /// Date times accept everything `--since` does, e.g. `2024-01-01 10:00` or `1h ago`
+    pub fn parse_value(&self, value: &str) -> Result<ParsedValue, QueryError> {
+        let invalid = |e: &str| {
+            QueryError::InvalidValue(format!("{} '{}' for column {}", e, value, self.name))
+        };
+
         match self.col_type {
             ColumnType::Integer => value
                 .parse::<i64>()
                 .map(ParsedValue::Integer)
-                .map_err(|e| format!("Failed to parse integer: {}", e)),
+                .map_err(|_| invalid("Expected an integer, found")),
+            ColumnType::Double => match value.parse::<f64>() {
+                Ok(double) if double.is_finite() => Ok(ParsedValue::Double(double)),
+                _ => Err(invalid("Expected a number, found")),
+            },
             ColumnType::String => Ok(ParsedValue::String(value.to_string())),
-            ColumnType::DateTime => NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S%.fZ")
-                .map(ParsedValue::DateTime)
-                .map_err(|e| format!("Failed to parse datetime: {}", e)),
+            ColumnType::DateTime => DateTimeParser::from_str(value)
+                .map(|datetime| ParsedValue::DateTime(datetime.to_utc()))
+                .map_err(|e| invalid(&format!("{}, found", e))),
         }
     }
 
     pub fn as_str(&self) -> &str {
-        self.name
+        &self.name
+    }
+
+    /// Used when the schema of the table can't be read. Configured partition columns
+    /// the documented ALB table lacks, e.g. `dt`, are added as strings.
+    pub fn defaults(partitions: &[Partition]) -> Vec<AthenaAlbColumn> {
+        let mut columns = ATHENA_ALB_COLUMNS.to_vec();
+
+        for partition in partitions {
+            if !columns
+                .iter()
+                .any(|column| column.name == partition.column())
+            {
+                columns.push(AthenaAlbColumn {
+                    name: Cow::Owned(partition.column().to_string()),
+                    col_type: ColumnType::String,
+                });
+            }
+        }
+
+        columns
+    }
+
+    /// Column of a discovered table. Documented ALB columns keep their type if the
+    /// table stores them as strings, e.g. `time`.
+    pub fn from_schema(column: &SchemaColumn) -> Self {
+        let data_type = column.data_type.to_lowercase();
+
+        let col_type = match data_type.split('(').next().unwrap_or_default() {
+            "tinyint" | "smallint" | "int" | "integer" | "bigint" => ColumnType::Integer,
+            "float" | "real" | "double" | "decimal" => ColumnType::Double,
+            "string" | "varchar" | "char" => ATHENA_ALB_COLUMNS
+                .iter()
+                .find(|default| default.name == column.name)
+                .map_or(ColumnType::String, |default| default.col_type.clone()),
+            _ => ColumnType::String,
+        };
+
+        AthenaAlbColumn {
+            name: Cow::Owned(column.name.clone()),
+            col_type,
+        }
     }
 }
 
-impl FromStr for AthenaAlbColumn {
-    type Err = QueryError;
+fn string_literal(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        ATHENA_ALB_COLUMNS
-            .iter()
-            .find(|&col| col.name == s)
-            .cloned()
-            .ok_or_else(|| QueryError::UnknownColumn(s.to_string()))
+/// Aliases are quoted unless they are plain lowercase identifiers, which Athena
+/// would lowercase anyway
+fn identifier(alias: &str) -> String {
+    let plain = alias.starts_with(|c: char| c.is_ascii_lowercase() || c == '_')
+        && alias
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_');
+
+    if plain {
+        alias.to_string()
+    } else {
+        format!("\"{}\"", alias.replace('"', "\"\""))
     }
 }
 
 pub struct QueryBuilder<'a> {
     table: &'a str,
+    columns: &'a [AthenaAlbColumn],
     where_clauses: Vec<String>,
     select_clauses: Vec<String>,
     group_by_clauses: Vec<String>,
     order_by_clauses: Vec<String>,
+    having_clauses: Vec<String>,
+    /// Result columns that are no table columns, e.g. aggregates and facets like `path`
+    aliases: Vec<String>,
+    /// Alias and expression of every aggregate, HAVING can't refer to aliases
+    aggregates: Vec<(String, String)>,
+    limit: Option<usize>,
+    window: Option<TimeDelta>,
 }
 
-impl QueryBuilder<'_> {
-    pub fn new<'a>(table: &'a str) -> QueryBuilder<'a> {
+impl<'a> QueryBuilder<'a> {
+    pub fn new(table: &'a str, columns: &'a [AthenaAlbColumn]) -> Self {
         QueryBuilder {
             table,
+            columns,
             where_clauses: vec![],
             select_clauses: vec![],
             group_by_clauses: vec![],
             order_by_clauses: vec![],
+            having_clauses: vec![],
+            aliases: vec![],
+            aggregates: vec![],
+            limit: None,
+            window: None,
         }
     }
 
+    fn column(&self, name: &str) -> Result<&'a AthenaAlbColumn, QueryError> {
+        self.columns
+            .iter()
+            .find(|column| column.name == name)
+            .ok_or_else(|| {
+                QueryError::UnknownColumn(
+                    name.to_string(),
+                    did_you_mean(name, self.columns.iter().map(|column| column.name.as_ref())),
+                )
+            })
+    }
+
+    /// Time window of the query, needed to compute rates
+    pub fn window(
+        &mut self,
+        since: Option<DateTime<FixedOffset>>,
+        until: Option<DateTime<FixedOffset>>,
+    ) -> Result<&mut Self, QueryError> {
+        self.window =
+            since.map(|since| until.map_or_else(Utc::now, |until| until.to_utc()) - since.to_utc());
+
+        Ok(self)
+    }
+
     pub fn select(&mut self, select_clause: &Vec<Select>) -> Result<&mut Self, QueryError> {
         if select_clause.is_empty() {
             return Ok(self);
         }
 
         for select in select_clause {
-            match select {
-                Select::All => self.select_clauses.push("*".to_string()),
-                Select::Column(col_str) => {
-                    let column = AthenaAlbColumn::from_str(col_str)?;
+            let expression = self.select_expression(select)?;
 
-                    self.select_clauses.push(column.as_str().to_string());
-                }
-                Select::Count(col_str_opt) => {
-                    if let Some(col_str) = col_str_opt {
-                        let column = AthenaAlbColumn::from_str(col_str)?;
+            match select.alias() {
+                Some(alias) => {
+                    self.select_clauses
+                        .push(format!("{} AS {}", expression, identifier(&alias)));
 
-                        self.select_clauses
-                            .push(format!("count({}) AS count", column.as_str()));
-                    } else {
-                        self.select_clauses.push("count(*) AS count".to_string());
+                    if select.is_aggregate() {
+                        self.aggregates.push((alias.clone(), expression));
                     }
-                }
-                Select::Average(col_str) => {
-                    let column = AthenaAlbColumn::from_str(col_str)?;
 
-                    self.select_clauses
-                        .push(format!("avg({}) AS avg", column.as_str()));
+                    self.aliases.push(alias);
                 }
+                None => self.select_clauses.push(expression),
             }
         }
 
         Ok(self)
     }
 
-    pub fn conditions(&mut self, where_clause: &Vec<Where>) -> Result<&mut Self, QueryError> {
-        if where_clause.is_empty() {
-            return Ok(self);
+    fn select_expression(&self, select: &Select) -> Result<String, QueryError> {
+        let aggregate = |function: &str, col_str: &str| -> Result<String, QueryError> {
+            let column = self.column(col_str)?;
+
+            Ok(format!("{}({})", function, column.as_str()))
+        };
+
+        match select {
+            Select::All => Ok("*".to_string()),
+            Select::Column(col_str) => Ok(self.column(col_str)?.as_str().to_string()),
+            Select::Count(None) => Ok("count(*)".to_string()),
+            Select::Count(Some(col_str)) => aggregate("count", col_str),
+            Select::Average(col_str) => aggregate("avg", col_str),
+            Select::Min(col_str) => aggregate("min", col_str),
+            Select::Max(col_str) => aggregate("max", col_str),
+            Select::Sum(col_str) => aggregate("sum", col_str),
+            Select::UniqueCount(col_str) => aggregate("approx_distinct", col_str),
+            Select::Percentile(col_str, percentile) => {
+                let column = self.column(col_str)?;
+
+                // Rounded, so 99.9 becomes 0.999 and not 0.9990000000000001
+                let fraction = (percentile / 100.0 * 1e9).round() / 1e9;
+
+                Ok(format!(
+                    "approx_percentile({}, {})",
+                    column.as_str(),
+                    fraction
+                ))
+            }
+            // The aggregate divided by the number of `per` periods in the window
+            Select::Rate(select, per) => {
+                let window = self.window.ok_or_else(|| {
+                    QueryError::InvalidAggregate("RATE requires SINCE".to_string())
+                })?;
+
+                let periods = window.num_milliseconds() as f64 / per.num_milliseconds() as f64;
+
+                Ok(format!(
+                    "CAST({} AS double) / {}",
+                    self.select_expression(select)?,
+                    periods
+                ))
+            }
+            Select::As(select, _) => self.select_expression(select),
         }
+    }
 
-        for where_clause in where_clause {
-            let (col_str, op, value) = match where_clause {
-                Where::Equals(col, val) => (col, "=", val),
-                Where::NotEquals(col, val) => (col, "!=", val),
-                Where::GreaterThan(col, val) => (col, ">", val),
-                Where::LessThan(col, val) => (col, "<", val),
-                Where::GreaterThanOrEqual(col, val) => (col, ">=", val),
-                Where::LessThanOrEqual(col, val) => (col, "<=", val),
-                Where::Like(col, val) => (col, "LIKE", val),
-                Where::In(col, values) => {
-                    let column = AthenaAlbColumn::from_str(col)?;
-
-                    let formatted_values = values
+    pub fn conditions(&mut self, conditions: &Vec<Condition>) -> Result<&mut Self, QueryError> {
+        for condition in conditions {
+            let where_clause = condition.render(&|where_clause| self.where_clause(where_clause))?;
+
+            self.where_clauses.push(where_clause);
+        }
+
+        Ok(self)
+    }
+
+    fn where_clause(&self, where_clause: &Where) -> Result<String, QueryError> {
+        let (col_str, op, value) = match where_clause {
+            Where::Equals(col, val) | Where::NotEquals(col, val) => {
+                let column = self.column(col)?;
+
+                if let Some(range) = column.status_code_range(val) {
+                    let not = match where_clause {
+                        Where::NotEquals(..) => "NOT ",
+                        _ => "",
+                    };
+
+                    return Ok(format!(
+                        "{} {}BETWEEN {} AND {}",
+                        column.as_str(),
+                        not,
+                        range.start(),
+                        range.end()
+                    ));
+                }
+
+                match where_clause {
+                    Where::NotEquals(..) => (col, "!=", val),
+                    _ => (col, "=", val),
+                }
+            }
+            Where::GreaterThan(col, val) => (col, ">", val),
+            Where::LessThan(col, val) => (col, "<", val),
+            Where::GreaterThanOrEqual(col, val) => (col, ">=", val),
+            Where::LessThanOrEqual(col, val) => (col, "<=", val),
+            // The pattern is never parsed as a value of the column
+            Where::Like(col, pattern) => {
+                let column = self.column(col)?;
+
+                return Ok(format!(
+                    "{} LIKE {}",
+                    column.as_str(),
+                    string_literal(pattern)
+                ));
+            }
+            Where::In(col, values) => {
+                let column = self.column(col)?;
+
+                let mut predicates: Vec<String> = vec![];
+                let mut formatted_values: Vec<String> = vec![];
+
+                for value in values {
+                    match column.status_code_range(value) {
+                        Some(range) => predicates.push(format!(
+                            "{} BETWEEN {} AND {}",
+                            column.as_str(),
+                            range.start(),
+                            range.end()
+                        )),
+                        None => formatted_values.push(column.prepare_value(value)?),
+                    }
+                }
+
+                if !formatted_values.is_empty() {
+                    predicates.insert(
+                        0,
+                        format!("{} IN ({})", column.as_str(), formatted_values.join(",")),
+                    );
+                }
+
+                return Ok(match predicates.len() {
+                    1 => predicates.remove(0),
+                    _ => format!("({})", predicates.join(" OR ")),
+                });
+            }
+        };
+
+        let column = self.column(col_str)?;
+
+        Ok(format!(
+            "{} {} {}",
+            column.as_str(),
+            op,
+            column.prepare_value(value)?
+        ))
+    }
+
+    /// Restricts the query to the partitions of the days between SINCE and UNTIL (or
+    /// now) and to the configured partition values
+    pub fn partitions(
+        &mut self,
+        partitions: &[Partition],
+        since: Option<DateTime<FixedOffset>>,
+        until: Option<DateTime<FixedOffset>>,
+    ) -> Result<&mut Self, QueryError> {
+        let mut dates = vec![];
+
+        for partition in partitions {
+            match partition {
+                Partition::Date { column, format } => dates.push((self.column(column)?, format)),
+                Partition::Values { column, values } if !values.is_empty() => {
+                    let column = self.column(column)?;
+
+                    let values = values
                         .iter()
-                        .map(|v| column.prepare_value(v))
-                        .collect::<Vec<String>>()
-                        .join(",");
+                        .map(|value| column.prepare_value(value))
+                        .collect::<Result<Vec<String>, QueryError>>()?;
+
                     self.where_clauses.push(format!(
                         "{} IN ({})",
                         column.as_str(),
-                        formatted_values
+                        values.join(",")
                     ));
-                    continue;
                 }
-            };
+                Partition::Values { .. } => {}
+            }
+        }
 
-            let column = AthenaAlbColumn::from_str(col_str)?;
+        let since = since.map(|since| since.with_timezone(&Utc).date_naive());
+        let until = until.map(|until| until.with_timezone(&Utc).date_naive());
 
-            self.where_clauses.push(format!(
-                "{} {} {}",
-                column.as_str(),
-                op,
-                column.prepare_value(value)
-            ));
+        if let Some(predicate) = Self::date_partitions(&dates, since, until)? {
+            self.where_clauses.push(predicate);
         }
 
         Ok(self)
     }
 
-    pub fn since(&mut self, since: Option<NaiveDateTime>) -> Result<&mut Self, QueryError> {
-        if let Some(since) = since {
-            let col = AthenaAlbColumn::from_str("day")?;
+    /// Consecutive days that share all but the last date partition form a group,
+    /// e.g. `year = '2024' AND month = '01' AND day BETWEEN '05' AND '31'`. With
+    /// too many groups the last partition is dropped, e.g. `year = '2024' AND month
+    /// BETWEEN '01' AND '12'`, until few enough groups remain.
+    fn date_partitions(
+        dates: &[(&AthenaAlbColumn, &String)],
+        since: Option<NaiveDate>,
+        until: Option<NaiveDate>,
+    ) -> Result<Option<String>, QueryError> {
+        let Some((first, first_format)) = dates.first() else {
+            return Ok(None);
+        };
+
+        let value = |column: &AthenaAlbColumn, format: &str, date: NaiveDate| {
+            column.prepare_value(&date.format(format).to_string())
+        };
+
+        let (since, until) = match (since, until) {
+            (Some(since), until) => (since, until.unwrap_or_else(|| Utc::now().date_naive())),
+            (None, Some(until)) => {
+                return Ok(Some(format!(
+                    "{} <= {}",
+                    first.as_str(),
+                    value(first, first_format, until)?
+                )))
+            }
+            (None, None) => return Ok(None),
+        };
+
+        // No day lies in the window, so no partition has to be read
+        if since > until {
+            return Ok(Some("FALSE".to_string()));
+        }
+
+        let days = since
+            .iter_days()
+            .take_while(|day| *day <= until)
+            .map(|day| {
+                dates
+                    .iter()
+                    .map(|(column, format)| value(column, format, day))
+                    .collect::<Result<Vec<String>, QueryError>>()
+            })
+            .collect::<Result<Vec<Vec<String>>, QueryError>>()?;
+
+        // A single partition always forms a single group, so this returns
+        for depth in (1..=dates.len()).rev() {
+            let groups = Self::partition_groups(&days, depth);
+
+            if groups.len() > MAX_PARTITION_GROUPS && depth > 1 {
+                continue;
+            }
+
+            let predicates = groups
+                .iter()
+                .map(|(prefix, from, to)| {
+                    let (range, _) = dates[depth - 1];
+
+                    let mut predicates = dates
+                        .iter()
+                        .zip(*prefix)
+                        .map(|((column, _), value)| format!("{} = {}", column.as_str(), value))
+                        .collect::<Vec<String>>();
+
+                    predicates.push(if from == to {
+                        format!("{} = {}", range.as_str(), from)
+                    } else {
+                        format!("{} BETWEEN {} AND {}", range.as_str(), from, to)
+                    });
+
+                    predicates.join(" AND ")
+                })
+                .collect::<Vec<String>>();
+
+            return Ok(match predicates.len() {
+                1 => predicates.into_iter().next(),
+                _ => Some(format!("(({}))", predicates.join(") OR ("))),
+            });
+        }
 
-            self.where_clauses.push(format!(
-                "{} >= '{}'",
-                col.as_str(),
-                since.format(DAY_FORMAT)
-            ));
+        Ok(None)
+    }
+
+    /// Runs of days with the same values of the first `depth - 1` partitions,
+    /// with the first and last value of partition `depth` in the run
+    fn partition_groups(days: &[Vec<String>], depth: usize) -> Vec<(&[String], &str, &str)> {
+        let mut groups: Vec<(&[String], &str, &str)> = vec![];
+
+        for values in days {
+            let (prefix, value) = (&values[..depth - 1], values[depth - 1].as_str());
+
+            match groups.last_mut() {
+                Some((other, _, to)) if *other == prefix => *to = value,
+                _ => groups.push((prefix, value, value)),
+            }
+        }
+
+        groups
+    }
+
+    pub fn since(&mut self, since: Option<DateTime<FixedOffset>>) -> Result<&mut Self, QueryError> {
+        if let Some(since) = since {
+            let since = since.with_timezone(&Utc);
 
             self.where_clauses
             .push(format!("parse_datetime(time,'yyyy-MM-dd''T''HH:mm:ss.SSSSSS''Z') >= parse_datetime('{}','yyyy-MM-dd-HH:mm:ss')", since.format(TIME_FORMAT)));
@@ -223,15 +673,9 @@ impl QueryBuilder<'_> {
         Ok(self)
     }
 
-    pub fn until(&mut self, until: Option<NaiveDateTime>) -> Result<&mut Self, QueryError> {
+    pub fn until(&mut self, until: Option<DateTime<FixedOffset>>) -> Result<&mut Self, QueryError> {
         if let Some(until) = until {
-            let col = AthenaAlbColumn::from_str("day")?;
-
-            self.where_clauses.push(format!(
-                "{} <= '{}'",
-                col.as_str(),
-                until.format(DAY_FORMAT)
-            ));
+            let until = until.with_timezone(&Utc);
 
             self.where_clauses
             .push(format!("parse_datetime(time,'yyyy-MM-dd''T''HH:mm:ss.SSSSSS''Z') <= parse_datetime('{}','yyyy-MM-dd-HH:mm:ss')", until.format(TIME_FORMAT)));
@@ -246,22 +690,40 @@ impl QueryBuilder<'_> {
         }
 
         for facet in facet_input {
-            let col = AthenaAlbColumn::from_str(facet.0.as_str())?;
-
-            let col_name = col.as_str().to_string();
-
-            match col.name {
+            match facet.0.as_str() {
+                // request_url normalized to its route, e.g. /users/<ID>
                 "path" => {
                     let regex = r"regexp_replace(regexp_replace(regexp_replace(regexp_replace(lower(request_url), '[0-9a-fA-F]{4,12}(?:-[0-9a-fA-F]{4,12}){0,4}', '<GUID>'),'https\:.*:443\/', ''),'\d+', '<ID>'),'\?.+','')";
 
                     self.group_by_clauses.push(regex.to_string());
 
-                    self.select_clauses.push(col_name);
+                    self.select_clauses.push(format!("{} AS path", regex));
+                    self.aliases.push("path".to_string());
+                }
+                // Calendar buckets of `time` in UTC
+                unit @ ("minute" | "hour" | "day" | "week" | "month") => {
+                    let col = self.column("time")?;
+
+                    let expression = format!(
+                        "date_trunc('{}', from_iso8601_timestamp({}))",
+                        unit,
+                        col.as_str()
+                    );
+
+                    self.select_clauses
+                        .push(format!("to_iso8601({}) AS {}", expression, unit));
+                    self.aliases.push(unit.to_string());
+
+                    self.group_by_clauses.push(expression);
                 }
-                _ => {
+                col_str => {
+                    let col = self.column(col_str)?;
+
+                    let col_name = col.as_str().to_string();
+
                     self.group_by_clauses.push(col_name.clone());
 
-                    self.select_clauses.push(col_name.clone());
+                    self.select_clauses.push(col_name);
                 }
             }
         }
@@ -269,6 +731,104 @@ impl QueryBuilder<'_> {
         Ok(self)
     }
 
+    /// Conditions on aggregate aliases, rendered with the aggregate expressions
+    pub fn having(&mut self, having: &[Condition]) -> Result<&mut Self, QueryError> {
+        for condition in having {
+            let having_clause = condition.render(&|having| self.having_clause(having))?;
+
+            self.having_clauses.push(having_clause);
+        }
+
+        Ok(self)
+    }
+
+    fn having_clause(&self, having: &Where) -> Result<String, QueryError> {
+        let expression = self
+            .aggregates
+            .iter()
+            .find(|(alias, _)| alias == having.column())
+            .map(|(_, expression)| expression)
+            .ok_or_else(|| {
+                QueryError::InvalidAggregate(format!(
+                    "HAVING requires an aggregate alias, found '{}'",
+                    having.column()
+                ))
+            })?;
+
+        // Values are numbers, checked by the parser
+        let having_clause = match having {
+            Where::Equals(_, value) => format!("{} = {}", expression, value),
+            Where::NotEquals(_, value) => format!("{} != {}", expression, value),
+            Where::GreaterThan(_, value) => format!("{} > {}", expression, value),
+            Where::LessThan(_, value) => format!("{} < {}", expression, value),
+            Where::GreaterThanOrEqual(_, value) => format!("{} >= {}", expression, value),
+            Where::LessThanOrEqual(_, value) => format!("{} <= {}", expression, value),
+            Where::In(_, values) => format!("{} IN ({})", expression, values.join(",")),
+            Where::Like(..) => {
+                return Err(QueryError::InvalidAggregate(
+                    "LIKE cannot be used on aggregates".to_string(),
+                ))
+            }
+        };
+
+        Ok(having_clause)
+    }
+
+    /// Groups by the start of the bucket a row's `time` falls into. Buckets of one
+    /// minute, hour or day use `date_trunc`, others are aligned to the epoch. The rows
+    /// are ordered by bucket unless ORDER BY is given.
+    pub fn timeseries(&mut self, bucket: Option<TimeDelta>) -> Result<&mut Self, QueryError> {
+        if let Some(bucket) = bucket {
+            let col = self.column("time")?;
+
+            let time = format!("from_iso8601_timestamp({})", col.as_str());
+
+            let expression = match bucket.num_seconds() {
+                60 => format!("date_trunc('minute', {})", time),
+                3600 => format!("date_trunc('hour', {})", time),
+                86400 => format!("date_trunc('day', {})", time),
+                seconds => format!(
+                    "from_unixtime(floor(to_unixtime({}) / {1}) * {1})",
+                    time, seconds
+                ),
+            };
+
+            self.select_clauses.insert(
+                0,
+                format!("to_iso8601({}) AS {}", expression, BUCKET_COLUMN),
+            );
+            self.group_by_clauses.push(expression);
+            self.aliases.push(BUCKET_COLUMN.to_string());
+        }
+
+        Ok(self)
+    }
+
+    pub fn order_by(&mut self, order_by: &[OrderBy]) -> Result<&mut Self, QueryError> {
+        if order_by.is_empty() && self.aliases.iter().any(|alias| alias == BUCKET_COLUMN) {
+            self.order_by_clauses.push(BUCKET_COLUMN.to_string());
+        }
+
+        for order_by in order_by {
+            let column = if self.aliases.contains(&order_by.column) {
+                identifier(&order_by.column)
+            } else {
+                self.column(&order_by.column)?.as_str().to_string()
+            };
+
+            self.order_by_clauses
+                .push(format!("{} {}", column, order_by.order));
+        }
+
+        Ok(self)
+    }
+
+    pub fn limit(&mut self, limit: Option<usize>) -> Result<&mut Self, QueryError> {
+        self.limit = limit;
+
+        Ok(self)
+    }
+
     pub fn build_query(&self) -> String {
         let capacity = 512;
         let mut query_string = String::with_capacity(capacity);
@@ -296,11 +856,131 @@ impl QueryBuilder<'_> {
                 .push_str(format!(" GROUP BY {}", self.group_by_clauses.join(", ")).as_str());
         }
 
+        if !self.having_clauses.is_empty() {
+            query_string
+                .push_str(format!(" HAVING {}", self.having_clauses.join(" AND ")).as_str());
+        }
+
         if !self.order_by_clauses.is_empty() {
             query_string
                 .push_str(format!(" ORDER BY {}", self.order_by_clauses.join(", ")).as_str());
         }
 
+        if let Some(limit) = self.limit {
+            query_string.push_str(format!(" LIMIT {}", limit).as_str());
+        }
+
         query_string
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn column(name: &'static str) -> AthenaAlbColumn {
+        AthenaAlbColumn {
+            name: Cow::Borrowed(name),
+            col_type: ColumnType::String,
+        }
+    }
+
+    fn date(value: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(value, "%Y-%m-%d").unwrap()
+    }
+
+    fn dt(since: Option<&str>, until: Option<&str>) -> Option<String> {
+        let (dt, format) = (column("dt"), "%Y-%m-%d".to_string());
+
+        QueryBuilder::date_partitions(&[(&dt, &format)], since.map(date), until.map(date)).unwrap()
+    }
+
+    fn year_month_day(since: &str, until: &str) -> Option<String> {
+        let (year, month, day) = (column("year"), column("month"), column("day"));
+        let (year_format, month_format, day_format) =
+            ("%Y".to_string(), "%m".to_string(), "%d".to_string());
+
+        QueryBuilder::date_partitions(
+            &[
+                (&year, &year_format),
+                (&month, &month_format),
+                (&day, &day_format),
+            ],
+            Some(date(since)),
+            Some(date(until)),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn single_partition_is_a_range() {
+        assert_eq!(
+            dt(Some("2024-01-30"), Some("2024-02-02")).as_deref(),
+            Some("dt BETWEEN '2024-01-30' AND '2024-02-02'")
+        );
+        assert_eq!(
+            dt(Some("2024-01-30"), Some("2024-01-30")).as_deref(),
+            Some("dt = '2024-01-30'")
+        );
+    }
+
+    #[test]
+    fn since_only_ends_today() {
+        let today = Utc::now().date_naive().format("%Y-%m-%d");
+
+        assert_eq!(
+            dt(Some("2024-01-30"), None),
+            Some(format!("dt BETWEEN '2024-01-30' AND '{today}'"))
+        );
+    }
+
+    #[test]
+    fn until_only_bounds_the_first_partition() {
+        assert_eq!(
+            dt(None, Some("2024-02-02")).as_deref(),
+            Some("dt <= '2024-02-02'")
+        );
+        assert_eq!(dt(None, None), None);
+    }
+
+    #[test]
+    fn empty_window_reads_nothing() {
+        assert_eq!(
+            dt(Some("2024-02-02"), Some("2024-01-30")).as_deref(),
+            Some("FALSE")
+        );
+    }
+
+    #[test]
+    fn nested_partitions_within_a_month() {
+        assert_eq!(
+            year_month_day("2024-03-05", "2024-03-07").as_deref(),
+            Some("year = '2024' AND month = '03' AND day BETWEEN '05' AND '07'")
+        );
+    }
+
+    #[test]
+    fn nested_partitions_across_months() {
+        assert_eq!(
+            year_month_day("2023-12-31", "2024-02-02").as_deref(),
+            Some(concat!(
+                "((year = '2023' AND month = '12' AND day = '31')",
+                " OR (year = '2024' AND month = '01' AND day BETWEEN '01' AND '31')",
+                " OR (year = '2024' AND month = '02' AND day BETWEEN '01' AND '02'))"
+            ))
+        );
+    }
+
+    #[test]
+    fn many_groups_constrain_a_shorter_prefix() {
+        assert_eq!(
+            year_month_day("2021-03-15", "2024-06-10").as_deref(),
+            Some(concat!(
+                "((year = '2021' AND month BETWEEN '03' AND '12')",
+                " OR (year = '2022' AND month BETWEEN '01' AND '12')",
+                " OR (year = '2023' AND month BETWEEN '01' AND '12')",
+                " OR (year = '2024' AND month BETWEEN '01' AND '06'))"
+            ))
+        );
+    }
+}
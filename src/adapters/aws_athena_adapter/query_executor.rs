@@ -1,4 +1,5 @@
-use aws_sdk_athena::types::QueryExecutionContext;
+use aws_sdk_athena::{operation::RequestId, types::QueryExecutionContext};
+use log::{debug, trace};
 
 use crate::query::QueryExecutionError;
 
@@ -28,6 +29,12 @@ impl<'a> QueryExecutor<'a> {
 
         match request.send().await {
             Ok(output) => {
+                trace!(
+                    "[{}] StartQueryExecution request id {}",
+                    self.client.data_source_id,
+                    output.request_id().unwrap_or("-")
+                );
+
                 let query_execution_id =
                     output
                         .query_execution_id
@@ -35,11 +42,14 @@ impl<'a> QueryExecutor<'a> {
                             "Query Execution ID not found",
                         )))?;
 
-                return Ok(query_execution_id);
-            }
-            Err(error) => {
-                return Err(QueryExecutionError::ClientError(error.to_string()));
+                debug!(
+                    "[{}] Started query execution {}",
+                    self.client.data_source_id, query_execution_id
+                );
+
+                Ok(query_execution_id)
             }
+            Err(error) => Err(QueryExecutionError::ClientError(error.to_string())),
         }
     }
 }
@@ -1,11 +1,17 @@
+use aws_sdk_athena::operation::RequestId;
 use aws_sdk_athena::{
-    operation::get_query_results::builders::GetQueryResultsFluentBuilder, types::Row,
+    operation::get_query_results::builders::GetQueryResultsFluentBuilder, types::ResultSet,
 };
+use futures::{stream, Stream};
+use log::{debug, trace};
 
 use crate::query::QueryExecutionError;
 
 use super::client::Client;
 
+/// Rows per result page, the most GetQueryResults returns
+pub const PAGE_SIZE: usize = 1000;
+
 pub struct QueryResultFetcher<'a> {
     client: &'a Client<'a>,
 }
@@ -15,60 +21,101 @@ impl<'a> QueryResultFetcher<'a> {
         Self { client }
     }
 
-    pub async fn get_query_results(
+    /// Pages of the result and whether another page follows, fetched lazily until
+    /// the last page. Only the first page has the column types.
+    pub fn pages<'b>(
+        &'b self,
+        query_execution_id: &'b str,
+    ) -> impl Stream<Item = Result<(ResultSet, bool), QueryExecutionError>> + 'b {
+        // `None` once the last page was fetched, otherwise the page number and token
+        let first_page = Some((1, None));
+
+        stream::try_unfold(first_page, move |state| async move {
+            let Some((page, next_token)) = state else {
+                return Ok(None);
+            };
+
+            debug!(
+                "[{}] Fetching result page {}",
+                self.client.data_source_id, page
+            );
+
+            let output = self
+                .build_request(query_execution_id, next_token.as_deref())
+                .send()
+                .await
+                .map_err(|error| QueryExecutionError::ClientError(error.to_string()))?;
+
+            trace!(
+                "[{}] GetQueryResults request id {}",
+                self.client.data_source_id,
+                output.request_id().unwrap_or("-")
+            );
+
+            let result_set = output.result_set.ok_or(QueryExecutionError::NoData)?;
+
+            let state = output.next_token.map(|token| (page + 1, Some(token)));
+
+            Ok(Some(((result_set, state.is_some()), state)))
+        })
+    }
+
+    /// Downloads the CSV file Athena writes the complete result to
+    pub async fn download_csv(
         &self,
         query_execution_id: &str,
-    ) -> Result<Vec<Row>, QueryExecutionError> {
-        let mut rows: Vec<Row> = Vec::new();
-
-        // Helper variable to not lose the next_token value
-        let mut temp_token: String;
-
-        let mut next_token: Option<&str> = None;
-
-        let mut page: u16 = 1;
-
-        let mut ran_first_time: bool = false;
-
-        let max_iterations: u16 = 10;
-
-        while page < max_iterations && (!ran_first_time || next_token.is_some()) {
-            println!("Fetching page {}...", page);
-
-            let result_request = self.build_request(query_execution_id, next_token);
-
-            match result_request.send().await {
-                Ok(output) => {
-                    let Some(mut rows_data) =
-                        output.result_set.and_then(|result_set| result_set.rows)
-                    else {
-                        return Err(QueryExecutionError::NoData);
-                    };
-
-                    rows.append(&mut rows_data);
-
-                    match output.next_token {
-                        Some(t) => {
-                            // t is only valid in this scope
-                            // clone on heap and assign to next_token
-                            temp_token = t.to_string();
-                            next_token = Some(&temp_token);
-                        }
-                        None => {
-                            next_token = None;
-                        }
-                    }
-                }
-                Err(error) => {
-                    return Err(QueryExecutionError::ClientError(error.to_string()));
-                }
-            }
-
-            ran_first_time = true;
-            page += 1;
-        }
+    ) -> Result<String, QueryExecutionError> {
+        debug!(
+            "[{}] Too many result pages, downloading the result CSV",
+            self.client.data_source_id
+        );
 
-        Ok(rows)
+        let output = self
+            .client
+            .get_query_execution()
+            .query_execution_id(query_execution_id)
+            .send()
+            .await?;
+
+        let location = output
+            .query_execution
+            .and_then(|query_execution| query_execution.result_configuration)
+            .and_then(|result_configuration| result_configuration.output_location)
+            .ok_or(QueryExecutionError::ClientError(String::from(
+                "Query output location not found",
+            )))?;
+
+        let (bucket, key) = location
+            .strip_prefix("s3://")
+            .and_then(|location| location.split_once('/'))
+            .ok_or_else(|| {
+                QueryExecutionError::ClientError(format!("Invalid output location {}", location))
+            })?;
+
+        let object = aws_sdk_s3::Client::new(&self.client.config)
+            .get_object()
+            .bucket(bucket)
+            .key(key)
+            .send()
+            .await
+            .map_err(|error| QueryExecutionError::ClientError(error.to_string()))?;
+
+        trace!(
+            "[{}] GetObject {} request id {}",
+            self.client.data_source_id,
+            location,
+            object.request_id().unwrap_or("-")
+        );
+
+        let bytes = object
+            .body
+            .collect()
+            .await
+            .map_err(|error| QueryExecutionError::ClientError(error.to_string()))?
+            .into_bytes();
+
+        String::from_utf8(bytes.to_vec())
+            .map_err(|error| QueryExecutionError::ParseError(error.to_string()))
     }
 
     fn build_request(
@@ -79,7 +126,8 @@ impl<'a> QueryResultFetcher<'a> {
         let builder = self
             .client
             .get_query_results()
-            .query_execution_id(query_execution_id);
+            .query_execution_id(query_execution_id)
+            .max_results(PAGE_SIZE as i32);
 
         match next_token {
             Some(token) => builder.next_token(token),
@@ -1,9 +1,11 @@
 use std::collections::HashMap;
 
-use aws_sdk_athena::types::Row;
-use serde_json::Value;
+use aws_sdk_athena::types::{ColumnInfo, Row};
+use chrono::{NaiveDateTime, SecondsFormat, TimeZone};
+use chrono_tz::Tz;
+use serde_json::{json, Value};
 
-use crate::query::QueryResult;
+use crate::query::{parse_timestamp, QueryResult};
 
 pub struct QueryResultProcessor;
 
@@ -12,35 +14,254 @@ impl QueryResultProcessor {
         Self {}
     }
 
-    pub fn process(&self, data: Vec<Row>) -> QueryResult {
+    /// Converts the rows to JSON values of the column types. NULL cells become
+    /// `null` and timestamps RFC 3339 in UTC, e.g. `2024-01-01T10:00:00.000Z`.
+    pub fn process(&self, columns: &[ColumnInfo], data: Vec<Row>) -> QueryResult {
         let mut result: QueryResult = vec![];
-        let mut headers: Vec<String> = vec![];
 
         for (index, row) in data.into_iter().enumerate() {
-            let row_data = row.data.unwrap();
-
-            if index == 0 {
-                // Headers
-                for datum in row_data {
-                    let header = datum.var_char_value.unwrap();
-                    headers.push(header);
-                }
-            } else {
-                // Data
-                let mut row_hash: HashMap<String, Value> = HashMap::new();
-
-                for (index, value) in row_data.iter().enumerate() {
-                    // TODO: clone is not great
-                    let key = headers.get(index).unwrap().clone();
-                    let value = value.var_char_value.clone().unwrap();
-
-                    row_hash.insert(key, Value::String(value));
-                }
-
-                result.push(row_hash);
+            let values = row
+                .data
+                .unwrap_or_default()
+                .into_iter()
+                .map(|datum| datum.var_char_value)
+                .collect::<Vec<Option<String>>>();
+
+            // SELECT results start with a row of the column names
+            if index == 0
+                && values.len() == columns.len()
+                && values
+                    .iter()
+                    .zip(columns)
+                    .all(|(value, column)| value.as_deref() == Some(column.name()))
+            {
+                continue;
             }
+
+            result.push(Self::row(columns, values));
         }
 
         result
     }
+
+    /// Rows of the result CSV Athena writes to S3, which starts with a header row
+    pub fn process_csv(&self, columns: &[ColumnInfo], csv: &str) -> QueryResult {
+        parse_csv(csv)
+            .into_iter()
+            .skip(1)
+            .map(|values| Self::row(columns, values))
+            .collect()
+    }
+
+    fn row(columns: &[ColumnInfo], values: Vec<Option<String>>) -> HashMap<String, Value> {
+        columns
+            .iter()
+            .zip(values)
+            .map(|(column, value)| {
+                let value = value.map_or(Value::Null, |value| Self::value(column, value));
+
+                (column.name().to_string(), value)
+            })
+            .collect()
+    }
+
+    /// Values that don't match their type are kept as strings, as are decimals
+    /// which a float would round
+    fn value(column: &ColumnInfo, value: String) -> Value {
+        let data_type = column.r#type().to_lowercase();
+
+        let typed = match data_type.as_str() {
+            "tinyint" | "smallint" | "integer" | "int" | "bigint" => {
+                value.parse::<i64>().ok().map(Value::from)
+            }
+            "float" | "real" | "double" => value
+                .parse::<f64>()
+                .ok()
+                .filter(|number| number.is_finite())
+                .map(Value::from),
+            "boolean" => value.parse::<bool>().ok().map(Value::from),
+            "timestamp" | "timestamp with time zone" => Self::timestamp(&value),
+            "json" => serde_json::from_str(&value).ok(),
+            _ => None,
+        };
+
+        typed.unwrap_or(Value::String(value))
+    }
+
+    /// `2024-01-01 10:00:00.000`, optionally followed by `UTC` or another time zone
+    fn timestamp(value: &str) -> Option<Value> {
+        let timestamp = parse_timestamp(&json!(value)).or_else(|| {
+            let (datetime, zone) = value.rsplit_once(' ')?;
+            let datetime = NaiveDateTime::parse_from_str(datetime, "%Y-%m-%d %H:%M:%S%.f").ok()?;
+
+            zone.parse::<Tz>()
+                .ok()?
+                .from_local_datetime(&datetime)
+                .earliest()
+                .map(|datetime| datetime.to_utc())
+        })?;
+
+        Some(json!(timestamp.to_rfc3339_opts(SecondsFormat::Millis, true)))
+    }
+}
+
+/// Athena quotes every value, so unquoted empty fields are NULL
+fn parse_csv(csv: &str) -> Vec<Vec<Option<String>>> {
+    let mut records = vec![];
+    let mut record = vec![];
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut in_quotes = false;
+
+    let end_field = |field: &mut String, quoted: &mut bool| {
+        let value = (*quoted || !field.is_empty()).then(|| std::mem::take(field));
+        *quoted = false;
+        value
+    };
+
+    let mut chars = csv.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if in_quotes && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' if in_quotes => in_quotes = false,
+            '"' if field.is_empty() => {
+                in_quotes = true;
+                quoted = true;
+            }
+            ',' if !in_quotes => record.push(end_field(&mut field, &mut quoted)),
+            '\n' if !in_quotes => {
+                record.push(end_field(&mut field, &mut quoted));
+                records.push(std::mem::take(&mut record));
+            }
+            '\r' if !in_quotes => {}
+            c => field.push(c),
+        }
+    }
+
+    if quoted || !field.is_empty() || !record.is_empty() {
+        record.push(end_field(&mut field, &mut quoted));
+        records.push(record);
+    }
+
+    records
+}
+
+#[cfg(test)]
+mod tests {
+    use aws_sdk_athena::types::Datum;
+
+    use super::*;
+
+    fn columns() -> Vec<ColumnInfo> {
+        [
+            ("status", "integer"),
+            ("duration", "double"),
+            ("cost", "decimal"),
+            ("success", "boolean"),
+            ("time", "timestamp"),
+            ("url", "varchar"),
+        ]
+        .into_iter()
+        .map(|(name, data_type)| {
+            ColumnInfo::builder()
+                .name(name)
+                .r#type(data_type)
+                .build()
+                .unwrap()
+        })
+        .collect()
+    }
+
+    fn row(values: &[Option<&str>]) -> Row {
+        Row::builder()
+            .set_data(Some(
+                values
+                    .iter()
+                    .map(|value| {
+                        Datum::builder()
+                            .set_var_char_value(value.map(String::from))
+                            .build()
+                    })
+                    .collect(),
+            ))
+            .build()
+    }
+
+    fn expected() -> QueryResult {
+        vec![
+            HashMap::from([
+                ("status".to_string(), json!(502)),
+                ("duration".to_string(), json!(0.25)),
+                ("cost".to_string(), json!("12345678901234567.89")),
+                ("success".to_string(), json!(false)),
+                ("time".to_string(), json!("2024-01-01T10:00:00.000Z")),
+                ("url".to_string(), json!("https://example.com/")),
+            ]),
+            HashMap::from([
+                ("status".to_string(), json!("n/a")),
+                ("duration".to_string(), Value::Null),
+                ("cost".to_string(), Value::Null),
+                ("success".to_string(), Value::Null),
+                ("time".to_string(), Value::Null),
+                ("url".to_string(), json!("")),
+            ]),
+        ]
+    }
+
+    #[test]
+    fn types_rows() {
+        let data = vec![
+            row(&[
+                Some("status"),
+                Some("duration"),
+                Some("cost"),
+                Some("success"),
+                Some("time"),
+                Some("url"),
+            ]),
+            row(&[
+                Some("502"),
+                Some("0.25"),
+                Some("12345678901234567.89"),
+                Some("false"),
+                Some("2024-01-01 10:00:00.000"),
+                Some("https://example.com/"),
+            ]),
+            row(&[Some("n/a"), None, None, None, None, Some("")]),
+        ];
+
+        assert_eq!(
+            QueryResultProcessor::new().process(&columns(), data),
+            expected()
+        );
+    }
+
+    #[test]
+    fn types_csv_rows() {
+        let csv = concat!(
+            "\"status\",\"duration\",\"cost\",\"success\",\"time\",\"url\"\n",
+            "\"502\",\"0.25\",\"12345678901234567.89\",\"false\",\"2024-01-01 10:00:00.000 UTC\",\"https://example.com/\"\n",
+            "\"n/a\",,,,,\"\"\n",
+        );
+
+        assert_eq!(
+            QueryResultProcessor::new().process_csv(&columns(), csv),
+            expected()
+        );
+    }
+
+    #[test]
+    fn keeps_quotes_and_line_breaks_in_csv_fields() {
+        assert_eq!(
+            parse_csv("\"a \"\"b\"\"\",\"c\nd\"\r\n,\"\""),
+            vec![
+                vec![Some("a \"b\"".to_string()), Some("c\nd".to_string())],
+                vec![None, Some(String::new())],
+            ]
+        );
+    }
 }
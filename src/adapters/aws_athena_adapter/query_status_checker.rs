@@ -1,12 +1,21 @@
+use std::time::{Duration, Instant};
+
 use aws_sdk_athena::{
     operation::get_query_execution::builders::GetQueryExecutionFluentBuilder,
-    types::QueryExecutionState,
+    types::{QueryExecutionState, QueryExecutionStatistics},
 };
 
-use crate::query::QueryExecutionError;
+use aws_sdk_athena::operation::RequestId;
+use log::trace;
+
+use crate::{progress::Progress, query::QueryExecutionError};
 
 use super::client::Client;
 
+/// Polls start this often and back off exponentially up to `MAX_INTERVAL`
+const FIRST_INTERVAL: Duration = Duration::from_millis(250);
+const MAX_INTERVAL: Duration = Duration::from_secs(5);
+
 pub struct QueryStatusChecker<'a> {
     client: &'a Client<'a>,
 }
@@ -16,52 +25,84 @@ impl<'a> QueryStatusChecker<'a> {
         Self { client }
     }
 
+    /// Waits until the query succeeded, at most the timeout of the client, and
+    /// returns its final statistics
     pub async fn poll_query_status(
         &self,
         query_execution_id: &str,
-    ) -> Result<(), QueryExecutionError> {
-        let max_retries: u32 = 10;
-        // possibly wait longer if status is queued
-        let wait_time_in_secs: u64 = 5;
+    ) -> Result<Option<QueryExecutionStatistics>, QueryExecutionError> {
+        let started = Instant::now();
+        let timeout = self.client.timeout;
 
-        let mut i: u32 = 0;
+        let mut interval = FIRST_INTERVAL;
+        let mut progress = Progress::new(self.client.data_source_id);
 
-        tokio::time::sleep(std::time::Duration::from_secs(wait_time_in_secs)).await;
+        loop {
+            let response = self.build_request(query_execution_id).send().await?;
 
-        while i < max_retries {
-            let response = self.build_request(&query_execution_id).send().await?;
+            trace!(
+                "[{}] GetQueryExecution request id {}",
+                self.client.data_source_id,
+                response.request_id().unwrap_or("-")
+            );
 
-            let Some(state) = response
+            let Some((status, statistics)) = response
                 .query_execution
-                .and_then(|qe| qe.status)
-                .and_then(|status| status.state)
+                .map(|query_execution| (query_execution.status, query_execution.statistics))
             else {
+                progress.finish();
                 return Err(QueryExecutionError::BadQueryStatus("Not Found".to_string()));
             };
 
-            if [QueryExecutionState::Cancelled, QueryExecutionState::Failed].contains(&state) {
-                return Err(QueryExecutionError::BadQueryStatus(state.to_string()));
-            }
+            let Some((state, reason)) =
+                status.and_then(|status| Some((status.state?, status.state_change_reason)))
+            else {
+                progress.finish();
+                return Err(QueryExecutionError::BadQueryStatus("Not Found".to_string()));
+            };
 
-            if state == QueryExecutionState::Succeeded {
-                break;
-            }
+            progress.update(
+                state.as_str(),
+                statistics
+                    .as_ref()
+                    .and_then(|statistics| statistics.data_scanned_in_bytes),
+            );
 
-            if i == max_retries {
-                return Err(QueryExecutionError::QueryTimeout);
+            match state {
+                QueryExecutionState::Succeeded => {
+                    progress.finish();
+                    return Ok(statistics);
+                }
+                QueryExecutionState::Cancelled | QueryExecutionState::Failed => {
+                    progress.finish();
+
+                    let message = match reason {
+                        Some(reason) => format!("{}: {}", state, reason),
+                        None => state.to_string(),
+                    };
+
+                    return Err(QueryExecutionError::BadQueryStatus(message));
+                }
+                _ => {}
             }
 
-            println!(
-                "Query state: {:?}. Retrying in {} seconds...",
-                state, wait_time_in_secs
+            let Some(remaining) = timeout.checked_sub(started.elapsed()) else {
+                progress.finish();
+                return Err(QueryExecutionError::QueryTimeout(timeout));
+            };
+
+            trace!(
+                "[{}] {} after {:.1}s, polling again in {}ms",
+                self.client.data_source_id,
+                state,
+                started.elapsed().as_secs_f64(),
+                interval.min(remaining).as_millis()
             );
 
-            tokio::time::sleep(std::time::Duration::from_secs(wait_time_in_secs)).await;
+            tokio::time::sleep(interval.min(remaining)).await;
 
-            i += 1;
+            interval = (interval * 2).min(MAX_INTERVAL);
         }
-
-        Ok(())
     }
 
     fn build_request(&self, query_execution_id: &str) -> GetQueryExecutionFluentBuilder {
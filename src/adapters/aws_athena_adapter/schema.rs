@@ -0,0 +1,113 @@
+use std::fs;
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{AwsAthenaALBLog, Config},
+    query::{QueryExecutionError, SchemaColumn},
+};
+
+use super::client::Client;
+
+/// Columns of an Athena table read from its Glue metadata, cached on disk per
+/// data source so queries don't have to look them up every time
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Schema {
+    pub table: String,
+    pub columns: Vec<SchemaColumn>,
+}
+
+impl Schema {
+    /// The cached schema of the data source, fetched and cached if there is none, it
+    /// belongs to another table or `refresh` is set
+    pub async fn get(
+        data_source_id: &str,
+        details: &AwsAthenaALBLog,
+        refresh: bool,
+    ) -> Result<Schema, QueryExecutionError> {
+        if !refresh {
+            if let Some(schema) = Self::cached(data_source_id, details) {
+                return Ok(schema);
+            }
+        }
+
+        let client = Client::new(&details.region).await;
+
+        let client = client
+            .catalog(&details.catalog)
+            .database(&details.database)
+            .workgroup(&details.workgroup)
+            .data_source_id(data_source_id);
+
+        let schema = Self::fetch(&client, &details.table).await?;
+
+        if let Err(e) = schema.save(data_source_id) {
+            warn!("[{}] Failed to cache the schema: {}", data_source_id, e);
+        }
+
+        Ok(schema)
+    }
+
+    /// The cached schema of the data source if it belongs to its table
+    pub fn cached(data_source_id: &str, details: &AwsAthenaALBLog) -> Option<Schema> {
+        Self::load(data_source_id).filter(|schema| schema.table == details.table)
+    }
+
+    pub async fn fetch(client: &Client<'_>, table: &str) -> Result<Schema, QueryExecutionError> {
+        let output = client
+            .get_table_metadata()
+            .catalog_name(client.catalog)
+            .database_name(client.database)
+            .table_name(table)
+            .work_group(client.workgroup)
+            .send()
+            .await
+            .map_err(|e| QueryExecutionError::ClientError(e.to_string()))?;
+
+        let metadata = output.table_metadata.ok_or(QueryExecutionError::NoData)?;
+
+        let columns = metadata
+            .columns()
+            .iter()
+            .map(|column| (column, false))
+            .chain(
+                metadata
+                    .partition_keys()
+                    .iter()
+                    .map(|column| (column, true)),
+            )
+            .map(|(column, partition)| SchemaColumn {
+                name: column.name().to_string(),
+                data_type: column.r#type().unwrap_or("string").to_string(),
+                partition,
+            })
+            .collect();
+
+        Ok(Schema {
+            table: table.to_string(),
+            columns,
+        })
+    }
+
+    fn load(data_source_id: &str) -> Option<Schema> {
+        let path = Config::cache_dir()
+            .ok()?
+            .join("schemas")
+            .join(format!("{}.json", data_source_id));
+
+        serde_json::from_str(&fs::read_to_string(path).ok()?).ok()
+    }
+
+    fn save(&self, data_source_id: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let dir = Config::cache_dir()?.join("schemas");
+
+        fs::create_dir_all(&dir)?;
+        fs::write(
+            dir.join(format!("{}.json", data_source_id)),
+            serde_json::to_string_pretty(self)?,
+        )?;
+
+        Ok(())
+    }
+}
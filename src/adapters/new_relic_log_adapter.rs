@@ -4,24 +4,36 @@ use query_executor::QueryExecutor;
 use crate::{
     config::{DataSource, DataSourceDetails, NewRelicLog},
     parsers::QueryInput,
-    query::{QueryError, QueryExecutionError, QueryResult},
+    query::{
+        having_matches, QueryError, QueryExecutionError, QueryOutput, QueryPlan, QueryResult,
+        SchemaColumn,
+    },
 };
 
-use super::QueryAdapter;
+use super::{ExecutionOptions, QueryAdapter};
 
 use async_trait::async_trait;
+use log::warn;
 
 mod query_builder;
 mod query_executor;
 
+pub use query_builder::MAX_LIMIT;
+
 pub struct NewRelicLogAdapter<'a> {
+    id: &'a str,
     details: &'a NewRelicLog,
+    options: ExecutionOptions,
 }
 
 impl<'a> NewRelicLogAdapter<'a> {
-    pub fn new(data_source: &'a DataSource) -> Self {
+    pub fn new(data_source: &'a DataSource, options: ExecutionOptions) -> Self {
         match &data_source.details {
-            DataSourceDetails::NewRelicLog(details) => Self { details },
+            DataSourceDetails::NewRelicLog(details) => Self {
+                id: &data_source.id,
+                details,
+                options,
+            },
             _ => panic!("NewRelicLogAdapter requires a NewRelicLog data source"),
         }
     }
@@ -29,21 +41,70 @@ impl<'a> NewRelicLogAdapter<'a> {
 
 #[async_trait]
 impl<'a> QueryAdapter<'a> for NewRelicLogAdapter<'a> {
-    async fn execute_query(&self, query: &str) -> Result<QueryResult, QueryExecutionError> {
+    async fn execute_query(&self, query: &str) -> Result<QueryOutput, QueryExecutionError> {
         let executor = QueryExecutor::new(&self.details.api_key, &self.details.account_id);
-        executor.execute_query(query).await
+        let mut rows = executor.execute_query(query).await?;
+
+        let max_rows = self.options.max_rows.unwrap_or(usize::MAX);
+        let truncated = rows.len() > max_rows;
+
+        if truncated {
+            rows.truncate(max_rows);
+
+            warn!(
+                "[{}] Stopped reading after {} rows, raise --max-rows for more",
+                self.id, max_rows
+            );
+        }
+
+        Ok(QueryOutput {
+            rows,
+            truncated,
+            statistics: None,
+        })
     }
 
-    fn build_query(&self, input: &'a QueryInput) -> Result<String, QueryError> {
+    async fn build_query(&self, input: &'a QueryInput) -> Result<String, QueryError> {
         let mut query = QueryBuilder::new(self.details.table.as_str());
 
         let query_string = query
             .select(&input.select)?
             .conditions(&input.conditions)?
+            .facet(&input.facet)?
+            .having(&input.having)?
+            .timeseries(input.timeseries)?
+            .order_by(&input.order_by)?
+            .limit(input.limit)?
             .since(input.since)?
             .until(input.until)?
             .build_query();
 
         Ok(query_string)
     }
+
+    /// NRQL has no EXPLAIN
+    async fn explain(
+        &self,
+        _query: &str,
+        _analyze: bool,
+    ) -> Result<QueryPlan, QueryExecutionError> {
+        Err(QueryExecutionError::Unsupported(
+            "New Relic can't explain queries".to_string(),
+        ))
+    }
+
+    /// The known columns, New Relic logs have no fixed schema
+    async fn schema(&self, _refresh: bool) -> Result<Vec<SchemaColumn>, QueryExecutionError> {
+        Ok(QueryBuilder::columns())
+    }
+
+    /// NRQL has no HAVING, so the rows are filtered here
+    fn post_process(&self, input: &'a QueryInput, result: &mut QueryResult) {
+        result.retain(|row| {
+            input
+                .having
+                .iter()
+                .all(|condition| condition.evaluate(&|having| having_matches(row, having)))
+        });
+    }
 }
@@ -1,35 +1,45 @@
-use chrono::NaiveDateTime;
+use chrono::{DateTime, FixedOffset, TimeDelta, Utc};
 
-use crate::parsers::{Select, Where};
-use crate::query::QueryError;
-use std::str::FromStr;
+use crate::error::did_you_mean;
+use crate::parsers::{Condition, Facet, OrderBy, Parser, Select, StatusCodeParser, Where};
+use crate::query::{QueryError, SchemaColumn};
+use std::{fmt, ops::RangeInclusive, str::FromStr};
 
 const TIME_FORMAT: &str = "%Y-%m-%dT%H:%M:%SZ";
 
+/// NRQL returns at most this many rows, `LIMIT MAX`
+pub const MAX_LIMIT: usize = 5000;
+
 const NEW_RELIC_LOG_COLUMNS: [NewRelicLogColumn; 6] = [
     NewRelicLogColumn {
         name: "response",
-        col_type: ColumnType::String,
+        col_type: ColumnType::StatusCode,
+        aliases: &["elb_status_code"],
     },
     NewRelicLogColumn {
         name: "logtype",
         col_type: ColumnType::String,
+        aliases: &[],
     },
     NewRelicLogColumn {
         name: "message",
         col_type: ColumnType::String,
+        aliases: &[],
     },
     NewRelicLogColumn {
         name: "timestamp",
         col_type: ColumnType::Integer,
+        aliases: &[],
     },
     NewRelicLogColumn {
         name: "clientip",
         col_type: ColumnType::String,
+        aliases: &[],
     },
     NewRelicLogColumn {
         name: "hostname",
         col_type: ColumnType::String,
+        aliases: &[],
     },
 ];
 
@@ -37,19 +47,71 @@ const NEW_RELIC_LOG_COLUMNS: [NewRelicLogColumn; 6] = [
 pub enum ColumnType {
     String,
     Integer,
+    /// HTTP status code stored as a string
+    StatusCode,
+}
+
+impl fmt::Display for ColumnType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ColumnType::String => write!(f, "string"),
+            ColumnType::Integer => write!(f, "integer"),
+            ColumnType::StatusCode => write!(f, "status code"),
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct NewRelicLogColumn {
     pub name: &'static str,
     pub col_type: ColumnType,
+    /// Names of the matching ALB columns, which the filter flags of `query` use
+    pub aliases: &'static [&'static str],
 }
 
 impl NewRelicLogColumn {
-    pub fn parse_value(&self, value: &str) -> String {
+    pub fn parse_value(&self, value: &str) -> Result<String, QueryError> {
         match self.col_type {
-            ColumnType::String => format!("'{}'", value),
-            ColumnType::Integer => value.to_string(),
+            ColumnType::String | ColumnType::StatusCode => {
+                Ok(format!("'{}'", value.replace('\'', "\\'")))
+            }
+            ColumnType::Integer => value
+                .parse::<i64>()
+                .map(|integer| integer.to_string())
+                .map_err(|_| {
+                    QueryError::InvalidValue(format!(
+                        "Expected an integer, found '{}' for column {}",
+                        value, self.name
+                    ))
+                }),
+        }
+    }
+
+    /// Status code class or range (e.g. `5xx`, `500-504`) given for a status code column
+    pub fn status_code_range(&self, value: &str) -> Option<RangeInclusive<u16>> {
+        if self.col_type != ColumnType::StatusCode || value.parse::<i64>().is_ok() {
+            return None;
+        }
+
+        StatusCodeParser::from_str(value).ok()
+    }
+
+    /// NRQL has no BETWEEN, so ranges compare the numeric value against both bounds
+    pub fn range_predicate(&self, range: &RangeInclusive<u16>, negate: bool) -> String {
+        if negate {
+            format!(
+                "(numeric({0}) < {1} OR numeric({0}) > {2})",
+                self.name,
+                range.start(),
+                range.end()
+            )
+        } else {
+            format!(
+                "(numeric({0}) >= {1} AND numeric({0}) <= {2})",
+                self.name,
+                range.start(),
+                range.end()
+            )
         }
     }
 
@@ -64,17 +126,48 @@ impl FromStr for NewRelicLogColumn {
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         NEW_RELIC_LOG_COLUMNS
             .iter()
-            .find(|&col| col.name == s)
+            .find(|&col| col.name == s || col.aliases.contains(&s))
             .cloned()
-            .ok_or_else(|| QueryError::UnknownColumn(s.to_string()))
+            .ok_or_else(|| {
+                QueryError::UnknownColumn(
+                    s.to_string(),
+                    did_you_mean(s, NEW_RELIC_LOG_COLUMNS.iter().map(|col| col.name)),
+                )
+            })
     }
 }
 
+/// `<n> <unit>` in the largest unit the duration is a multiple of, e.g. `5 minutes`
+fn duration(duration: &TimeDelta) -> String {
+    let seconds = duration.num_seconds();
+
+    let (amount, unit) = [
+        (604800, "week"),
+        (86400, "day"),
+        (3600, "hour"),
+        (60, "minute"),
+    ]
+    .into_iter()
+    .find(|(unit, _)| seconds % unit == 0)
+    .map_or((seconds, "second"), |(unit, name)| (seconds / unit, name));
+
+    format!("{} {}{}", amount, unit, if amount == 1 { "" } else { "s" })
+}
+
 pub struct QueryBuilder<'a> {
     table: &'a str,
     where_clauses: Vec<String>,
     select_clauses: Vec<String>,
+    facet_clauses: Vec<String>,
+    order_by_clauses: Vec<String>,
+    limit_clause: Option<String>,
     time_clauses: Vec<String>,
+    /// Alias and expression of every aggregate, NRQL orders facets by the expression
+    aggregates: Vec<(String, String)>,
+    /// Whether rows are ordered after the query because NRQL can't
+    ordered_later: bool,
+    /// Whether rows are filtered after the query because NRQL has no HAVING
+    filtered_later: bool,
 }
 
 impl<'a> QueryBuilder<'a> {
@@ -83,127 +176,172 @@ impl<'a> QueryBuilder<'a> {
             table,
             where_clauses: vec![],
             select_clauses: vec![],
+            facet_clauses: vec![],
+            order_by_clauses: vec![],
+            limit_clause: None,
             time_clauses: vec![],
+            aggregates: vec![],
+            ordered_later: false,
+            filtered_later: false,
         }
     }
 
+    pub fn columns() -> Vec<SchemaColumn> {
+        NEW_RELIC_LOG_COLUMNS
+            .iter()
+            .map(|column| SchemaColumn {
+                name: column.name.to_string(),
+                data_type: column.col_type.to_string(),
+                partition: false,
+            })
+            .collect()
+    }
+
     pub fn select(&mut self, select_clause: &Vec<Select>) -> Result<&mut Self, QueryError> {
         if select_clause.is_empty() {
             return Ok(self);
         }
 
         for select in select_clause {
-            match select {
-                Select::All => self.select_clauses.push("*".to_string()),
-                Select::Column(col_str) => {
-                    let column = NewRelicLogColumn::from_str(col_str)?;
-                    self.select_clauses.push(column.name.to_string());
-                }
-                Select::Count(col_str_opt) => {
-                    if let Some(col_str) = col_str_opt {
-                        let column = NewRelicLogColumn::from_str(col_str)?;
-                        self.select_clauses.push(format!("count({})", column.name));
-                    } else {
-                        self.select_clauses.push("count(*)".to_string());
+            let expression = Self::select_expression(select)?;
+
+            match select.alias() {
+                Some(alias) => {
+                    self.select_clauses.push(format!(
+                        "{} AS '{}'",
+                        expression,
+                        alias.replace('\'', "\\'")
+                    ));
+
+                    if select.is_aggregate() {
+                        self.aggregates.push((alias, expression));
                     }
                 }
-                Select::Average(col_str) => {
-                    let column = NewRelicLogColumn::from_str(col_str)?;
-                    self.select_clauses
-                        .push(format!("average({})", column.name));
-                }
+                None => self.select_clauses.push(expression),
             }
         }
 
         Ok(self)
     }
 
-    pub fn conditions(&mut self, where_clause: &Vec<Where>) -> Result<&mut Self, QueryError> {
-        if where_clause.is_empty() {
-            return Ok(self);
+    fn select_expression(select: &Select) -> Result<String, QueryError> {
+        let aggregate = |function: &str, col_str: &str| -> Result<String, QueryError> {
+            let column = NewRelicLogColumn::from_str(col_str)?;
+
+            Ok(format!("{}({})", function, column.name))
+        };
+
+        match select {
+            Select::All => Ok("*".to_string()),
+            Select::Column(col_str) => Ok(NewRelicLogColumn::from_str(col_str)?.name.to_string()),
+            Select::Count(None) => Ok("count(*)".to_string()),
+            Select::Count(Some(col_str)) => aggregate("count", col_str),
+            Select::Average(col_str) => aggregate("average", col_str),
+            Select::Min(col_str) => aggregate("min", col_str),
+            Select::Max(col_str) => aggregate("max", col_str),
+            Select::Sum(col_str) => aggregate("sum", col_str),
+            Select::UniqueCount(col_str) => aggregate("uniqueCount", col_str),
+            Select::Percentile(col_str, percentile) => {
+                let column = NewRelicLogColumn::from_str(col_str)?;
+
+                Ok(format!("percentile({}, {})", column.name, percentile))
+            }
+            Select::Rate(select, per) => Ok(format!(
+                "rate({}, {})",
+                Self::select_expression(select)?,
+                duration(per)
+            )),
+            Select::As(select, _) => Self::select_expression(select),
         }
+    }
 
-        for condition in where_clause {
-            match condition {
-                Where::Equals(col, value) => {
-                    let column = NewRelicLogColumn::from_str(col)?;
-                    self.where_clauses.push(format!(
-                        "{} = {}",
-                        column.name,
-                        column.parse_value(value)
-                    ));
-                }
-                Where::NotEquals(col, value) => {
-                    let column = NewRelicLogColumn::from_str(col)?;
-                    self.where_clauses.push(format!(
-                        "{} != {}",
-                        column.name,
-                        column.parse_value(value)
-                    ));
-                }
-                Where::In(col, values) => {
-                    let column = NewRelicLogColumn::from_str(col)?;
-                    let formatted_values = values
-                        .iter()
-                        .map(|v| column.parse_value(v))
-                        .collect::<Vec<_>>()
-                        .join(", ");
-                    self.where_clauses
-                        .push(format!("{} IN ({})", column.name, formatted_values));
-                }
-                Where::Like(col, pattern) => {
-                    let column = NewRelicLogColumn::from_str(col)?;
-                    self.where_clauses
-                        .push(format!("{} LIKE '{}'", column.name, pattern));
+    pub fn conditions(&mut self, conditions: &Vec<Condition>) -> Result<&mut Self, QueryError> {
+        for condition in conditions {
+            let where_clause = condition.render(&Self::where_clause)?;
+
+            self.where_clauses.push(where_clause);
+        }
+
+        Ok(self)
+    }
+
+    fn where_clause(condition: &Where) -> Result<String, QueryError> {
+        let where_clause = match condition {
+            Where::Equals(col, value) => {
+                let column = NewRelicLogColumn::from_str(col)?;
+
+                if let Some(range) = column.status_code_range(value) {
+                    return Ok(column.range_predicate(&range, false));
                 }
-                Where::GreaterThan(col, value) => {
-                    let column = NewRelicLogColumn::from_str(col)?;
-                    self.where_clauses.push(format!(
-                        "{} > {}",
-                        column.name,
-                        column.parse_value(value)
-                    ));
+
+                format!("{} = {}", column.name, column.parse_value(value)?)
+            }
+            Where::NotEquals(col, value) => {
+                let column = NewRelicLogColumn::from_str(col)?;
+
+                if let Some(range) = column.status_code_range(value) {
+                    return Ok(column.range_predicate(&range, true));
                 }
-                Where::LessThan(col, value) => {
-                    let column = NewRelicLogColumn::from_str(col)?;
-                    self.where_clauses.push(format!(
-                        "{} < {}",
-                        column.name,
-                        column.parse_value(value)
-                    ));
+
+                format!("{} != {}", column.name, column.parse_value(value)?)
+            }
+            Where::In(col, values) => {
+                let column = NewRelicLogColumn::from_str(col)?;
+
+                let mut predicates: Vec<String> = vec![];
+                let mut formatted_values: Vec<String> = vec![];
+
+                for value in values {
+                    match column.status_code_range(value) {
+                        Some(range) => predicates.push(column.range_predicate(&range, false)),
+                        None => formatted_values.push(column.parse_value(value)?),
+                    }
                 }
-                Where::GreaterThanOrEqual(col, value) => {
-                    let column = NewRelicLogColumn::from_str(col)?;
-                    self.where_clauses.push(format!(
-                        "{} >= {}",
-                        column.name,
-                        column.parse_value(value)
-                    ));
+
+                if !formatted_values.is_empty() {
+                    predicates.insert(
+                        0,
+                        format!("{} IN ({})", column.name, formatted_values.join(", ")),
+                    );
                 }
-                Where::LessThanOrEqual(col, value) => {
-                    let column = NewRelicLogColumn::from_str(col)?;
-                    self.where_clauses.push(format!(
-                        "{} <= {}",
-                        column.name,
-                        column.parse_value(value)
-                    ));
+
+                match predicates.len() {
+                    1 => predicates.remove(0),
+                    _ => format!("({})", predicates.join(" OR ")),
                 }
-                _ => panic!("Unsupported where clause: {:?}", condition),
             }
-        }
+            Where::Like(col, pattern) => {
+                let column = NewRelicLogColumn::from_str(col)?;
+                format!("{} LIKE '{}'", column.name, pattern.replace('\'', "\\'"))
+            }
+            Where::GreaterThan(col, value) => {
+                let column = NewRelicLogColumn::from_str(col)?;
+                format!("{} > {}", column.name, column.parse_value(value)?)
+            }
+            Where::LessThan(col, value) => {
+                let column = NewRelicLogColumn::from_str(col)?;
+                format!("{} < {}", column.name, column.parse_value(value)?)
+            }
+            Where::GreaterThanOrEqual(col, value) => {
+                let column = NewRelicLogColumn::from_str(col)?;
+                format!("{} >= {}", column.name, column.parse_value(value)?)
+            }
+            Where::LessThanOrEqual(col, value) => {
+                let column = NewRelicLogColumn::from_str(col)?;
+                format!("{} <= {}", column.name, column.parse_value(value)?)
+            }
+        };
 
-        Ok(self)
+        Ok(where_clause)
     }
 
-    pub fn since(&mut self, since: Option<NaiveDateTime>) -> Result<&mut Self, QueryError> {
+    pub fn since(&mut self, since: Option<DateTime<FixedOffset>>) -> Result<&mut Self, QueryError> {
         if let Some(since) = since {
+            let since = since.with_timezone(&Utc);
             let col = NewRelicLogColumn::from_str("timestamp")?;
 
-            self.where_clauses.push(format!(
-                "{} >= {}",
-                col.as_str(),
-                since.and_utc().timestamp_millis()
-            ));
+            self.where_clauses
+                .push(format!("{} >= {}", col.as_str(), since.timestamp_millis()));
 
             self.time_clauses
                 .push(format!("SINCE '{}'", since.format(TIME_FORMAT)));
@@ -212,15 +350,13 @@ impl<'a> QueryBuilder<'a> {
         Ok(self)
     }
 
-    pub fn until(&mut self, until: Option<NaiveDateTime>) -> Result<&mut Self, QueryError> {
+    pub fn until(&mut self, until: Option<DateTime<FixedOffset>>) -> Result<&mut Self, QueryError> {
         if let Some(until) = until {
+            let until = until.with_timezone(&Utc);
             let col = NewRelicLogColumn::from_str("timestamp")?;
 
-            self.where_clauses.push(format!(
-                "{} <= {}",
-                col.as_str(),
-                until.and_utc().timestamp_millis()
-            ));
+            self.where_clauses
+                .push(format!("{} <= {}", col.as_str(), until.timestamp_millis()));
 
             self.time_clauses
                 .push(format!("UNTIL '{}'", until.format(TIME_FORMAT)));
@@ -229,6 +365,104 @@ impl<'a> QueryBuilder<'a> {
         Ok(self)
     }
 
+    pub fn facet(&mut self, facet_input: &Vec<Facet>) -> Result<&mut Self, QueryError> {
+        for facet in facet_input {
+            let facet_clause = match facet.0.as_str() {
+                // Calendar buckets of `timestamp`, aliased like the Athena columns
+                unit @ ("minute" | "hour" | "day" | "week" | "month") => {
+                    let col = NewRelicLogColumn::from_str("timestamp")?;
+
+                    let function = match unit {
+                        "day" => "date",
+                        unit => unit,
+                    };
+
+                    format!("{}Of({}) AS {}", function, col.as_str(), unit)
+                }
+                col_str => NewRelicLogColumn::from_str(col_str)?.name.to_string(),
+            };
+
+            self.facet_clauses.push(facet_clause);
+        }
+
+        Ok(self)
+    }
+
+    /// NRQL has no HAVING, the caller filters the rows after the query instead
+    pub fn having(&mut self, having: &[Condition]) -> Result<&mut Self, QueryError> {
+        for condition in having {
+            for having in condition.leaves() {
+                if !self
+                    .aggregates
+                    .iter()
+                    .any(|(alias, _)| alias == having.column())
+                {
+                    return Err(QueryError::InvalidAggregate(format!(
+                        "HAVING requires an aggregate alias, found '{}'",
+                        having.column()
+                    )));
+                }
+            }
+
+            self.filtered_later = true;
+        }
+
+        Ok(self)
+    }
+
+    /// NRQL can only order facets by aggregate functions. Any other ordering is left
+    /// to the caller, which sorts the rows after the query.
+    pub fn order_by(&mut self, order_by: &[OrderBy]) -> Result<&mut Self, QueryError> {
+        if order_by.is_empty() {
+            return Ok(self);
+        }
+
+        let clauses = order_by
+            .iter()
+            .map(|order_by| {
+                self.aggregates
+                    .iter()
+                    .find(|(alias, _)| alias == &order_by.column)
+                    .map(|(_, expression)| format!("{} {}", expression, order_by.order))
+            })
+            .collect::<Option<Vec<String>>>();
+
+        match clauses {
+            Some(clauses) if !self.facet_clauses.is_empty() => self.order_by_clauses = clauses,
+            _ => self.ordered_later = true,
+        }
+
+        Ok(self)
+    }
+
+    /// Without native ordering or filtering all rows are fetched, so the caller can
+    /// order and filter them before applying the limit
+    pub fn limit(&mut self, limit: Option<usize>) -> Result<&mut Self, QueryError> {
+        let fetch_all = self.ordered_later || self.filtered_later;
+
+        self.limit_clause = match limit {
+            Some(limit) if fetch_all || limit >= MAX_LIMIT => Some("LIMIT MAX".to_string()),
+            Some(limit) => Some(format!("LIMIT {}", limit)),
+            // FACET only returns the top 10 facets by default
+            None if self.filtered_later && !self.facet_clauses.is_empty() => {
+                Some("LIMIT MAX".to_string())
+            }
+            None => None,
+        };
+
+        Ok(self)
+    }
+
+    /// `TIMESERIES <n> <unit>`
+    pub fn timeseries(&mut self, bucket: Option<TimeDelta>) -> Result<&mut Self, QueryError> {
+        if let Some(bucket) = bucket {
+            self.time_clauses
+                .push(format!("TIMESERIES {}", duration(&bucket)));
+        }
+
+        Ok(self)
+    }
+
     pub fn build_query(&self) -> String {
         let mut query = String::with_capacity(512);
 
@@ -244,6 +478,18 @@ impl<'a> QueryBuilder<'a> {
             query.push_str(&format!(" WHERE {}", self.where_clauses.join(" AND ")));
         }
 
+        if !self.facet_clauses.is_empty() {
+            query.push_str(&format!(" FACET {}", self.facet_clauses.join(", ")));
+        }
+
+        if !self.order_by_clauses.is_empty() {
+            query.push_str(&format!(" ORDER BY {}", self.order_by_clauses.join(", ")));
+        }
+
+        if let Some(limit_clause) = &self.limit_clause {
+            query.push_str(&format!(" {}", limit_clause));
+        }
+
         if !self.time_clauses.is_empty() {
             query.push_str(&format!(" {}", self.time_clauses.join(" ")));
         }
@@ -1,10 +1,14 @@
 use std::collections::HashMap;
 
 use chrono::DateTime;
+use log::trace;
 use reqwest::Client;
 use serde_json::{json, Value};
 
-use crate::query::{QueryExecutionError, QueryResult};
+use crate::{
+    query::{QueryExecutionError, QueryResult},
+    timeseries::{format_bucket, BUCKET_COLUMN},
+};
 
 const URL: &str = "https://api.newrelic.com/graphql";
 
@@ -36,7 +40,8 @@ impl<'a> QueryExecutor<'a> {
                 }}
             }}
             "#,
-            self.account_id, query
+            self.account_id,
+            query.replace('\\', "\\\\").replace('"', "\\\"")
         );
 
         let response = self
@@ -51,6 +56,16 @@ impl<'a> QueryExecutor<'a> {
             .await
             .map_err(|e| QueryExecutionError::ClientError(e.to_string()))?;
 
+        trace!(
+            "NerdGraph responded {}, request id {}",
+            response.status(),
+            response
+                .headers()
+                .get("x-request-id")
+                .and_then(|id| id.to_str().ok())
+                .unwrap_or("-")
+        );
+
         let json_response: Value = response
             .json()
             .await
@@ -69,15 +84,11 @@ impl<'a> QueryExecutor<'a> {
         let query_result: Result<QueryResult, QueryExecutionError> = results
             .iter()
             .map(|result| {
-                let mut row: HashMap<String, Value> = HashMap::new();
-
-                if let Some(logtype) = result["logtype"].as_str() {
-                    row.insert("logtype".to_string(), Value::String(logtype.to_string()));
-                }
-
-                if let Some(message) = result["message"].as_str() {
-                    row.insert("message".to_string(), Value::String(message.to_string()));
-                }
+                // Keep every attribute so correlated rows can be joined on any column
+                let mut row: HashMap<String, Value> = result
+                    .as_object()
+                    .map(|attributes| attributes.clone().into_iter().collect())
+                    .unwrap_or_default();
 
                 if let Some(timestamp) = result["timestamp"].as_f64() {
                     let naive_datetime = DateTime::from_timestamp_millis(timestamp as i64)
@@ -90,6 +101,38 @@ impl<'a> QueryExecutor<'a> {
                     );
                 }
 
+                // percentile() nests its value under the percentile, e.g. {"95": 1.2}
+                for value in row.values_mut() {
+                    if let Some(percentile) =
+                        value
+                            .as_object()
+                            .and_then(|object| match object.iter().next() {
+                                Some((key, percentile))
+                                    if object.len() == 1 && key.parse::<f64>().is_ok() =>
+                                {
+                                    Some(percentile.clone())
+                                }
+                                _ => None,
+                            })
+                    {
+                        *value = percentile;
+                    }
+                }
+
+                // TIMESERIES rows carry their bucket as epoch seconds
+                if let Some(begin) = result["beginTimeSeconds"].as_f64() {
+                    let start = DateTime::from_timestamp(begin as i64, 0)
+                        .ok_or("Invalid bucket")
+                        .map_err(|e| QueryExecutionError::ParseError(e.to_string()))?;
+
+                    row.remove("beginTimeSeconds");
+                    row.remove("endTimeSeconds");
+                    row.insert(
+                        BUCKET_COLUMN.to_string(),
+                        Value::String(format_bucket(start)),
+                    );
+                }
+
                 Ok(row)
             })
             .collect();
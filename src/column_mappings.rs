@@ -1,11 +1,25 @@
+use log::warn;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fs::File;
-use std::io::BufReader;
+use std::io::{BufReader, ErrorKind};
 use std::path::Path;
 use std::sync::LazyLock;
 
-pub static COLUMN_MAPPINGS: LazyLock<Vec<Mapping>> = LazyLock::new(|| load_mappings().unwrap());
+/// Mappings from `column_mappings.json` in the working directory, none without it
+pub static COLUMN_MAPPINGS: LazyLock<Vec<Mapping>> = LazyLock::new(|| {
+    load_mappings().unwrap_or_else(|e| {
+        let missing = e
+            .downcast_ref::<std::io::Error>()
+            .is_some_and(|e| e.kind() == ErrorKind::NotFound);
+
+        if !missing {
+            warn!("Ignoring column_mappings.json: {}", e);
+        }
+
+        vec![]
+    })
+});
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Mapping {
@@ -38,8 +52,8 @@ pub fn get_mapping<'a>(
     for mapping in COLUMN_MAPPINGS.iter() {
         if mapping.from.data_source_ids.contains(&from_id.to_string())
             && mapping.to.data_source_ids.contains(&to_id.to_string())
-            && mapping.from.column == from_col.to_string()
-            && mapping.to.column == to_col.to_string()
+            && mapping.from.column == from_col
+            && mapping.to.column == to_col
         {
             return Some(&mapping.mapping);
         }
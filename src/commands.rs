@@ -1,26 +1,36 @@
-use chrono::NaiveDateTime;
+use std::time::Duration;
+
+use chrono::{DateTime, FixedOffset, TimeDelta};
 use clap::{Parser, Subcommand};
 
 mod configure;
 mod query;
+mod schema;
 
 pub use crate::commands::configure::configure;
 pub use crate::commands::query::query;
+pub use crate::commands::schema::schema;
 
 use crate::{
-    config::DataSource,
-    parsers::{DatasetParser, DateTimeParser, DomainParser, Parser as _},
+    formatters::OutputFormat,
+    parallel_querier::DEFAULT_CONCURRENCY,
+    parsers::{
+        BucketParser, DateTimeParser, DomainParser, DurationParser, OrderBy, Parser as _,
+        StatusCodeParser,
+    },
 };
 
 #[derive(Subcommand, Debug)]
 pub enum Commands {
-    Query(QueryArgs),
+    Query(Box<QueryArgs>),
     Configure(ConfigureArgs),
+    /// Print the columns of a data source
+    Schema(Box<SchemaArgs>),
 }
 
 impl Default for Commands {
     fn default() -> Self {
-        Self::Query(QueryArgs::default())
+        Self::Query(Box::default())
     }
 }
 
@@ -35,10 +45,24 @@ pub struct ConfigureArgs {
     pub update: bool,
 }
 
+#[derive(Parser, Debug)]
+pub struct SchemaArgs {
+    /// DataSource ID
+    pub data_source: String,
+
+    /// Read the schema from the table metadata instead of the cache
+    #[arg(long)]
+    pub refresh: bool,
+
+    /// Output format
+    #[arg(long, short = 'f', value_enum, default_value_t = OutputFormat::Table)]
+    pub format: OutputFormat,
+}
+
 #[derive(Parser, Debug, Default)]
 pub struct QueryArgs {
-    /// List of ELB Status Codes, e.g. -c=200,300 or -c=5xx
-    #[arg(short, long, value_delimiter = ',')]
+    /// List of status codes, classes or ranges, e.g. -c=200,300 or -c=5xx,404 or -c=500-504
+    #[arg(short, long, value_delimiter = ',', value_parser = |s: &str| StatusCodeParser::from_str(s).map(|_| s.to_string()))]
     code: Vec<String>,
 
     /// List of domain names, e.g. -d=example.com OR subdomain names -d=sub1,sub2 (must have configured a default domain)
@@ -59,22 +83,75 @@ pub struct QueryArgs {
     // target: Option<String>,
 
     /// DataSource IDs
-    #[arg(short = 'i', long = "data-source-ids", required_unless_present = "raw", value_delimiter = ',', value_parser = |s: &str| DatasetParser::from_id(s).ok_or("DataSource not found"))]
-    data_sources: Vec<DataSource>,
-
-    /// Since - can be a Date -s=2022-01-01 or (partial) DateTime -s="2022-01-01 00:00" or Duration -s="1 HOUR AGO"
+    #[arg(
+        short = 'i',
+        long = "data-source-ids",
+        required_unless_present = "raw",
+        value_delimiter = ','
+    )]
+    data_sources: Vec<String>,
+
+    /// Since - can be a Date -s=2022-01-01, (partial) DateTime -s="2022-01-01 00:00", RFC 3339, epoch, -s=yesterday, -s="last monday 09:00" or Duration -s=30m -s="1 HOUR AGO", optionally with an offset or time zone -s="2022-01-01 09:00 Europe/Berlin"
     #[arg(long, short = 's', value_parser = |s: &str| DateTimeParser::from_str(s))]
-    since: Option<NaiveDateTime>,
+    since: Option<DateTime<FixedOffset>>,
 
-    /// Until - can be a Date -u=2022-01-01 or (partial) DateTime -u="2022-01-01 00:00" or Duration -u="1 HOUR AGO"
+    /// Until - can be a Date -u=2022-01-01, (partial) DateTime -u="2022-01-01 00:00", RFC 3339, epoch, -u=today, -u=now or Duration -u=30m -u="1 HOUR AGO", optionally with an offset or time zone -u="2022-01-01 17:00 +02:00"
     #[arg(long, short = 'u', value_parser = |s: &str| DateTimeParser::from_str(s))]
-    until: Option<NaiveDateTime>,
+    until: Option<DateTime<FixedOffset>>,
 
-    /// List of Count By Columns - e.g. --count-by=path,method --count-by=day
-    #[arg(long, value_delimiter = ',')]
+    /// Shorthand for --since relative to now, e.g. --last=2h or --last="30 minutes"
+    #[arg(long, conflicts_with_all = ["since", "raw"], value_parser = |s: &str| DurationParser::from_str(s))]
+    last: Option<TimeDelta>,
+
+    /// Count the rows per time bucket instead of listing them, e.g. --bucket=5m, --bucket=1h or --bucket=1d
+    #[arg(long, conflicts_with = "raw", value_parser = |s: &str| BucketParser::from_str(s))]
+    bucket: Option<TimeDelta>,
+
+    /// List of Count By Columns, path (the normalized request_url) or a calendar bucket (minute, hour, day, week, month) - e.g. --count-by=path,request_method --count-by=day
+    #[arg(long, value_delimiter = ',', conflicts_with = "raw")]
     count_by: Vec<String>,
 
-    /// Raw query string - e.g. --raw="SELECT elb_status_code, COUNT(*) FROM data1, data2 SINCE 2 days ago GROUP BY elb_status_code"
+    /// Sort the results, e.g. --sort=count:desc or --sort=elb_status_code,time:desc
+    #[arg(long, value_delimiter = ',')]
+    sort: Vec<OrderBy>,
+
+    /// Maximum number of rows, applied per data source and to the combined results
+    #[arg(long, value_parser = |s: &str| s.parse::<usize>().ok().filter(|limit| *limit > 0).ok_or("Expected a positive number"))]
+    limit: Option<usize>,
+
+    /// Maximum number of rows read per data source, the rows beyond are dropped with a warning
+    #[arg(long, value_parser = |s: &str| s.parse::<usize>().ok().filter(|max_rows| *max_rows > 0).ok_or("Expected a positive number"))]
+    max_rows: Option<usize>,
+
+    /// Stop Athena queries running longer than this, e.g. --timeout=10m (overrides the timeout of the data sources)
+    #[arg(long, value_parser = |s: &str| DurationParser::from_str(s).and_then(|timeout| timeout.to_std().map_err(|_| "Expected a positive duration")))]
+    timeout: Option<Duration>,
+
+    /// Maximum number of data sources queried at the same time
+    #[arg(long, default_value_t = DEFAULT_CONCURRENCY)]
+    concurrency: usize,
+
+    /// Output format
+    #[arg(long, short = 'f', value_enum, default_value_t = OutputFormat::Json)]
+    format: OutputFormat,
+
+    /// Write the results to a file instead of stdout, e.g. -o=result.csv (- for stdout)
+    #[arg(long, short = 'o')]
+    output: Option<String>,
+
+    /// Print the queries, including the correlated query, without running them
+    #[arg(long, conflicts_with = "explain")]
+    dry_run: bool,
+
+    /// Print the Athena query plans with their estimated scan size instead of the results
+    #[arg(long)]
+    explain: bool,
+
+    /// Run the queries for --explain to measure every step of the plan (billed like the query)
+    #[arg(long, requires = "explain")]
+    analyze: bool,
+
+    /// Raw query string - e.g. --raw="SELECT elb_status_code, COUNT(*) FROM data1, data2 SINCE 2 days ago GROUP BY elb_status_code" or --raw="SELECT count(*) FROM data1 SINCE 1 day ago TIMESERIES 1h"
     #[arg(long, required_unless_present = "data_sources")]
     raw: Option<String>,
 }
@@ -1,14 +1,20 @@
-use std::collections::HashMap;
-
-use inquire::{Confirm, Select, Text};
-
-use crate::config::{
-    AwsAthenaALBLog, Config, DataSource, DataSourceDetails, DataSourceType, NewRelicLog,
+use std::{collections::HashMap, fmt};
+
+use chrono_tz::{Tz, TZ_VARIANTS};
+use inquire::{Confirm, InquireError, Select, Text};
+use log::warn;
+
+use crate::{
+    config::{
+        AwsAthenaALBLog, Config, ConfigError, DataSource, DataSourceDetails, DataSourceType,
+        NewRelicLog, Partition,
+    },
+    error::did_you_mean,
 };
 
 use super::ConfigureArgs;
 
-pub fn configure(args: ConfigureArgs) -> Result<(), Box<dyn std::error::Error>> {
+pub fn configure(args: ConfigureArgs) -> Result<(), ConfigError> {
     if args.get_path {
         let _ = Config::file_path().inspect(|path| println!("{}", path));
         return Ok(());
@@ -29,6 +35,10 @@ pub fn configure(args: ConfigureArgs) -> Result<(), Box<dyn std::error::Error>>
         )?);
     }
 
+    if config.timezone.is_none() || prompt_yes_no("Do you want to update the time zone?")? {
+        config.timezone = Some(prompt_timezone(config.timezone.as_ref())?);
+    }
+
     if prompt_yes_no("Do you want to add a data source?")? {
         let mut defaults: HashMap<&str, String> = HashMap::new();
 
@@ -64,14 +74,16 @@ pub fn configure(args: ConfigureArgs) -> Result<(), Box<dyn std::error::Error>>
         }
     }
 
-    save_configuration(config);
+    save_configuration(config)
+}
 
-    Ok(())
+impl From<InquireError> for ConfigError {
+    fn from(e: InquireError) -> Self {
+        ConfigError::Prompt(e.to_string())
+    }
 }
 
-fn add_data_source(
-    defaults: &HashMap<&str, String>,
-) -> Result<DataSource, Box<dyn std::error::Error>> {
+fn add_data_source(defaults: &HashMap<&str, String>) -> Result<DataSource, ConfigError> {
     let name = prompt_string("Enter a name for the data source", None).unwrap_or_default();
     let id = prompt_string("Enter an ID for the data source", None).unwrap_or_default();
     let source_type = prompt_options("Choose a source type", &DataSourceType::all())?;
@@ -83,6 +95,8 @@ fn add_data_source(
             let workgroup = prompt_string("Enter the workgroup name", defaults.get("workgroup"))?;
             let database = prompt_string("Enter the database name", defaults.get("database"))?;
             let table = prompt_string("Enter the table name", None).unwrap_or_default();
+            let partitions =
+                prompt_options("Choose the partition layout", &PartitionLayout::all())?;
 
             DataSourceDetails::AwsAthenaALBLog(AwsAthenaALBLog {
                 region,
@@ -90,6 +104,8 @@ fn add_data_source(
                 workgroup,
                 database,
                 table,
+                partitions: partitions.partitions(),
+                timeout_secs: None,
             })
         }
         DataSourceType::NewRelicLog => {
@@ -113,16 +129,57 @@ fn add_data_source(
     })
 }
 
-fn prompt_yes_no(question: &str) -> Result<bool, Box<dyn std::error::Error>> {
+/// Common partition layouts of ALB log tables, others can be set in the config file
+#[derive(Debug, Clone, Copy)]
+enum PartitionLayout {
+    Day,
+    YearMonthDay,
+    Dt,
+    None,
+}
+
+impl PartitionLayout {
+    fn all() -> [PartitionLayout; 4] {
+        [
+            PartitionLayout::Day,
+            PartitionLayout::YearMonthDay,
+            PartitionLayout::Dt,
+            PartitionLayout::None,
+        ]
+    }
+
+    fn partitions(self) -> Vec<Partition> {
+        match self {
+            PartitionLayout::Day => Partition::default_layout(),
+            PartitionLayout::YearMonthDay => vec![
+                Partition::date("year", "%Y"),
+                Partition::date("month", "%m"),
+                Partition::date("day", "%d"),
+            ],
+            PartitionLayout::Dt => vec![Partition::date("dt", "%Y-%m-%d")],
+            PartitionLayout::None => vec![],
+        }
+    }
+}
+
+impl fmt::Display for PartitionLayout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PartitionLayout::Day => write!(f, "day=YYYY/MM/DD"),
+            PartitionLayout::YearMonthDay => write!(f, "year=YYYY/month=MM/day=DD"),
+            PartitionLayout::Dt => write!(f, "dt=YYYY-MM-DD"),
+            PartitionLayout::None => write!(f, "Not partitioned"),
+        }
+    }
+}
+
+fn prompt_yes_no(question: &str) -> Result<bool, ConfigError> {
     let answer = Confirm::new(question).with_default(false).prompt()?;
 
     Ok(answer)
 }
 
-fn prompt_string(
-    prompt: &str,
-    default: Option<&String>,
-) -> Result<String, Box<dyn std::error::Error>> {
+fn prompt_string(prompt: &str, default: Option<&String>) -> Result<String, ConfigError> {
     let mut text = Text::new(prompt);
 
     if let Some(default) = default {
@@ -132,7 +189,32 @@ fn prompt_string(
     Ok(text.prompt()?)
 }
 
-fn prompt_options<T>(prompt: &str, options: &[T]) -> Result<T, Box<dyn std::error::Error>>
+fn prompt_timezone(default: Option<&String>) -> Result<String, ConfigError> {
+    let default = default
+        .cloned()
+        .unwrap_or_else(|| Tz::UTC.name().to_string());
+
+    loop {
+        let timezone = prompt_string(
+            "Enter the time zone for dates and results (e.g., Europe/Berlin)",
+            Some(&default),
+        )?;
+
+        if timezone.parse::<Tz>().is_ok() {
+            return Ok(timezone);
+        }
+
+        match did_you_mean(&timezone, TZ_VARIANTS.iter().map(|tz| tz.name())) {
+            Some(suggestion) => warn!(
+                "Unknown time zone: {}, did you mean `{}`?",
+                timezone, suggestion
+            ),
+            None => warn!("Unknown time zone: {}", timezone),
+        }
+    }
+}
+
+fn prompt_options<T>(prompt: &str, options: &[T]) -> Result<T, ConfigError>
 where
     T: Clone + std::fmt::Display + ToString,
 {
@@ -143,11 +225,10 @@ where
     Ok(selection)
 }
 
-fn save_configuration(config: Config) -> () {
-    confy::store("fivexx", "config", config).expect("Failed to save configuration");
+fn save_configuration(config: Config) -> Result<(), ConfigError> {
+    confy::store("fivexx", "config", config).map_err(|e| ConfigError::Save(e.to_string()))?;
 
-    match Config::file_path() {
-        Ok(path) => println!("Saved config to {:?}", path),
-        Err(_) => println!("Failed to save configuration"),
-    }
+    println!("Saved config to {:?}", Config::file_path()?);
+
+    Ok(())
 }
@@ -1,190 +1,242 @@
-use std::{collections::HashMap, fs::File, io::Write};
+use std::{fs::File, io::Write};
 
-use serde_json::{json, Value};
+use chrono::Utc;
+use serde_json::json;
 
 use crate::{
-    adapters::{AdapterFactory, QueryAdapter},
-    column_mappings::get_mapping,
-    config::DataSource,
-    formatters::{CSVFormatter, Formatter, JSONFormatter},
-    parsers::{CorrelateCondition, QueryInput, QueryParser, Select, Where},
-    query::QueryResult,
+    adapters::ExecutionOptions,
+    config::{DataSource, CONFIG},
+    correlator::Correlator,
+    error::Error,
+    formatters::OutputFormat,
+    pager::page,
+    parallel_querier::{DataSourceError, ParallelQuerier},
+    parsers::{
+        Condition, DatasetParser, Facet, Order, OrderBy, QueryInput, QueryParser, Select, Where,
+    },
+    statistics::RunStatistics,
 };
 
 use super::QueryArgs;
 
-pub async fn query(args: QueryArgs) {
+pub async fn query(args: QueryArgs) -> Result<(), Error> {
     let data_sources: Vec<DataSource>;
-    let query_input: QueryInput;
+    let mut query_input: QueryInput;
 
-    if let Some(query_string) = args.raw {
-        let parsed_query = QueryParser::parse(&query_string).unwrap();
+    if let Some(query_string) = &args.raw {
+        // Without data sources every query fails to parse, report why instead
+        DatasetParser::require_config()?;
+
+        let parsed_query = QueryParser::parse(query_string).map_err(|error| Error::Parse {
+            query: query_string.clone(),
+            error,
+        })?;
 
         query_input = parsed_query.0;
         data_sources = parsed_query.1;
     } else {
         let mut select: Vec<Select> = vec![];
-        let mut conditions: Vec<Where> = vec![];
-
-        select.push(Select::Column("time".to_string()));
-        select.push(Select::Column("client_ip".to_string()));
-        select.push(Select::Column("elb_status_code".to_string()));
-        select.push(Select::Column("domain_name".to_string()));
-        // select.push(Select::Column("request_method".to_string()));
-        select.push(Select::Column("request_url".to_string()));
+        let mut conditions: Vec<Condition> = vec![];
+
+        let facet = args.count_by.into_iter().map(Facet).collect::<Vec<Facet>>();
+
+        if args.bucket.is_some() || !facet.is_empty() {
+            select.push(Select::Count(None));
+        } else {
+            select.push(Select::Column("time".to_string()));
+            select.push(Select::Column("client_ip".to_string()));
+            select.push(Select::Column("elb_status_code".to_string()));
+            select.push(Select::Column("domain_name".to_string()));
+            // select.push(Select::Column("request_method".to_string()));
+            select.push(Select::Column("request_url".to_string()));
+        }
 
         if !args.code.is_empty() {
-            conditions.push(Where::In("elb_status_code".to_string(), args.code));
+            conditions.push(Where::In("elb_status_code".to_string(), args.code).into());
         }
 
         if !args.domain.is_empty() {
-            conditions.push(Where::In("domain_name".to_string(), args.domain));
+            conditions.push(Where::In("domain_name".to_string(), args.domain).into());
         }
 
         if !args.method.is_empty() {
-            conditions.push(Where::In("request_method".to_string(), args.method));
+            conditions.push(Where::In("request_method".to_string(), args.method).into());
         }
 
         if let Some(url) = args.request_url {
             if url.contains("%") {
-                conditions.push(Where::Like("request_url".to_string(), url));
+                conditions.push(Where::Like("request_url".to_string(), url).into());
             } else {
-                conditions.push(Where::Equals("request_url".to_string(), url));
+                conditions.push(Where::Equals("request_url".to_string(), url).into());
             }
         }
 
         query_input = QueryInput {
             select,
             conditions,
-            facet: vec![],
-            since: args.since,
+            facet,
+            having: vec![],
+            since: args
+                .last
+                .map(|last| (Utc::now() - last).fixed_offset())
+                .or(args.since),
             until: args.until,
+            timeseries: args.bucket,
+            order_by: vec![],
+            limit: None,
             correlate: None,
         };
 
-        data_sources = args.data_sources;
+        if !query_input.facet.is_empty() && query_input.timeseries.is_none() {
+            query_input.order_by.push(OrderBy {
+                column: "count".to_string(),
+                order: Order::Desc,
+            });
+        }
+
+        data_sources = args
+            .data_sources
+            .iter()
+            .map(|id| DatasetParser::parse_id(id))
+            .collect::<Result<Vec<DataSource>, Error>>()?;
     }
 
-    let mut results: QueryResult = vec![];
+    if !args.sort.is_empty() {
+        query_input.order_by = args.sort;
+    }
 
-    for data_source in data_sources.iter() {
-        let adapter_factory = AdapterFactory::new();
-        let adapter = adapter_factory.create_adapter(&data_source);
+    if args.limit.is_some() {
+        query_input.limit = args.limit;
+    }
 
-        let query = adapter.build_query(&query_input).unwrap();
+    let options = ExecutionOptions {
+        max_rows: args.max_rows,
+        timeout: args.timeout,
+        offline: args.dry_run,
+    };
 
-        println!("\n{}\n", query);
+    let querier = ParallelQuerier::new(&data_sources)
+        .concurrency(args.concurrency)
+        .options(options);
 
-        match adapter.execute_query(&query).await {
-            Ok(mut result) => {
-                for row in &mut result {
-                    row.insert(
-                        "data_source_id".to_string(),
-                        Value::String(data_source.id.clone()),
-                    );
-                }
+    if args.dry_run {
+        return dry_run(&querier, &query_input, options).await;
+    }
 
-                results.extend(result);
-            }
-            Err(e) => println!("{:?}", e),
+    if args.explain {
+        return explain(&querier, &query_input, args.analyze).await;
+    }
+
+    let (mut results, mut errors, mut statistics) = querier.execute(&query_input).await;
+
+    if let Some(correlate) = &query_input.correlate {
+        let (correlated_errors, correlated_statistics) = Correlator::new(correlate)
+            .concurrency(args.concurrency)
+            .options(options)
+            .correlate(&mut results)
+            .await;
+
+        errors.extend(correlated_errors);
+        statistics.extend(correlated_statistics);
+    }
+
+    let price_per_tb = CONFIG.athena_price_per_tb();
+
+    // Only Athena reports statistics, otherwise the rows are printed on their own
+    let metadata =
+        (!statistics.is_empty()).then(|| json!({ "statistics": statistics.to_json(price_per_tb) }));
+
+    let mut formatted = args
+        .format
+        .format_with_metadata(results, CONFIG.timezone(), metadata)
+        .map_err(|e| Error::Output(e.to_string()))?;
+
+    if !formatted.is_empty() && !formatted.ends_with('\n') {
+        formatted.push('\n');
+    }
+
+    let written = match args.output.as_deref() {
+        None | Some("-") if args.format == OutputFormat::Table => page(&formatted),
+        None | Some("-") => std::io::stdout().write_all(formatted.as_bytes()),
+        Some(path) => File::create(path).and_then(|mut file| file.write_all(formatted.as_bytes())),
+    };
+
+    written.map_err(|e| Error::Output(e.to_string()))?;
+
+    if !statistics.is_empty() {
+        statistics.print_summary(price_per_tb);
+    }
+
+    data_sources_result(errors)
+}
+
+/// Prints the query of every data source and the correlated query template
+async fn dry_run(
+    querier: &ParallelQuerier<'_>,
+    query_input: &QueryInput,
+    options: ExecutionOptions,
+) -> Result<(), Error> {
+    let mut errors = vec![];
+
+    for query in querier.build_queries(query_input).await {
+        match query {
+            Ok((id, query)) => println!("-- {}\n{}\n", id, query),
+            Err(e) => errors.push(e),
         }
     }
 
     if let Some(correlate) = &query_input.correlate {
-        let adapter_factory = AdapterFactory::new();
-
-        for row in &mut results {
-            let mut correlated_query_input = *correlate.query_input.clone();
-
-            for dependent_condition in &correlate.dependent_conditions {
-                match dependent_condition {
-                    CorrelateCondition::Is { parent, child } => {
-                        let default: HashMap<String, String> = HashMap::new();
-
-                        let mapping = get_mapping(
-                            row["data_source_id"].as_str().unwrap(),
-                            &correlate.data_source.id,
-                            parent,
-                            child,
-                        )
-                        .unwrap_or(&default);
-
-                        if let Some(parent_value) = row.get(parent) {
-                            match parent_value {
-                                Value::String(str_value) => {
-                                    let value = match mapping.get(str_value) {
-                                        Some(val) => val,
-                                        None => str_value,
-                                    };
-
-                                    correlated_query_input
-                                        .conditions
-                                        .push(Where::Equals(child.clone(), value.clone()));
-                                }
-                                _ => continue,
-                            }
-                        } else {
-                            continue; // Skip this condition if parent value is not found
-                        }
-                    }
-                    CorrelateCondition::Within {
-                        parent,
-                        child: _,
-                        delta,
-                    } => {
-                        if let Some(parent_value) = row.get(parent) {
-                            match parent_value {
-                                Value::String(str_value) => {
-                                    let parent_datetime = chrono::NaiveDateTime::parse_from_str(
-                                        str_value,
-                                        "%Y-%m-%dT%H:%M:%S%.fZ",
-                                    )
-                                    .unwrap();
-
-                                    correlated_query_input.since = Some(parent_datetime - *delta);
-                                    correlated_query_input.until = Some(parent_datetime + *delta);
-                                }
-                                _ => continue,
-                            }
-                        } else {
-                            continue; // Skip this condition if parent value is not found
-                        }
-                    }
-                };
-            }
-            {
-                let adapter = adapter_factory.create_adapter(&correlate.data_source);
+        match Correlator::new(correlate).options(options).template().await {
+            Ok((query, description)) => println!(
+                "-- {} (correlated, {})\n{}\n",
+                correlate.data_source.id, description, query
+            ),
+            Err(e) => errors.push(e),
+        }
+    }
 
-                let query = adapter.build_query(&correlated_query_input).unwrap();
+    data_sources_result(errors)
+}
 
-                println!("\nCorrelated Query: {}\n", query);
+/// Prints the plan of the query of every data source
+async fn explain(
+    querier: &ParallelQuerier<'_>,
+    query_input: &QueryInput,
+    analyze: bool,
+) -> Result<(), Error> {
+    let mut statistics = RunStatistics::default();
+    let mut errors = vec![];
+
+    for plan in querier.explain(query_input, analyze).await {
+        match plan {
+            Ok((id, plan)) => {
+                println!("-- {}\n{}", id, plan.plan);
+
+                if let Some(estimated_scan) = plan.estimated_scan {
+                    println!("-- Estimated scan: {}", estimated_scan);
+                }
 
-                match adapter.execute_query(&query).await {
-                    Ok(correlated_result) => {
-                        let correlated_result = json!(correlated_result);
+                println!();
 
-                        // TODO: use id of dataset instead
-                        row.insert("correlated".to_string(), correlated_result);
-                    }
-                    Err(e) => println!("Error executing correlated query: {:?}", e),
+                if let Some(query_statistics) = plan.statistics {
+                    statistics.add(&id, query_statistics);
                 }
             }
+            Err(e) => errors.push(e),
         }
     }
 
-    // let formatter = CSVFormatter::default();
-    // let formatted = formatter.format(results).unwrap();
-
-    // // write to file
-    // let mut file = File::create("result.csv").unwrap();
-    // file.write_all(formatted.as_bytes()).unwrap();
-
-    let formatter = JSONFormatter {};
-    let formatted = formatter.format(results);
+    if !statistics.is_empty() {
+        statistics.print_summary(CONFIG.athena_price_per_tb());
+    }
 
-    // write to file
-    let mut file = File::create("result.json").unwrap();
-    file.write_all(formatted.as_bytes()).unwrap();
+    data_sources_result(errors)
+}
 
-    // println!("{:?}", formatted);
+fn data_sources_result(errors: Vec<DataSourceError>) -> Result<(), Error> {
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::DataSources(errors))
+    }
 }
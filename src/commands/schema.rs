@@ -0,0 +1,39 @@
+use std::collections::HashMap;
+
+use serde_json::json;
+
+use crate::{
+    adapters::AdapterFactory, config::CONFIG, error::Error, parallel_querier::DataSourceError,
+    parsers::DatasetParser,
+};
+
+use super::SchemaArgs;
+
+pub async fn schema(args: SchemaArgs) -> Result<(), Error> {
+    let data_source = DatasetParser::parse_id(&args.data_source)?;
+    let adapter = AdapterFactory::new().create_adapter(&data_source);
+
+    let columns = adapter.schema(args.refresh).await.map_err(|e| {
+        Error::DataSources(vec![DataSourceError::Execution(data_source.id.clone(), e)])
+    })?;
+
+    let rows = columns
+        .into_iter()
+        .map(|column| {
+            HashMap::from([
+                ("name".to_string(), json!(column.name)),
+                ("type".to_string(), json!(column.data_type)),
+                ("partition".to_string(), json!(column.partition)),
+            ])
+        })
+        .collect();
+
+    let formatted = args
+        .format
+        .format(rows, CONFIG.timezone())
+        .map_err(|e| Error::Output(e.to_string()))?;
+
+    println!("{}", formatted.trim_end());
+
+    Ok(())
+}
@@ -1,13 +1,19 @@
 use std::fmt::{self};
+use std::path::PathBuf;
 use std::sync::LazyLock;
 use std::{
     fmt::{Display, Formatter},
     str::FromStr,
 };
 
+use chrono_tz::Tz;
 use serde::{Deserialize, Serialize};
 
-pub static CONFIG: LazyLock<Config> = LazyLock::new(|| Config::load().unwrap());
+use crate::statistics::DEFAULT_PRICE_PER_TB;
+
+/// The loaded config, empty if it can't be loaded. Commands needing data sources
+/// report the reason with [`Config::load`].
+pub static CONFIG: LazyLock<Config> = LazyLock::new(|| Config::load().unwrap_or_default());
 
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub enum DataSourceType {
@@ -63,6 +69,42 @@ pub struct AwsAthenaALBLog {
     pub workgroup: String,
     pub database: String,
     pub table: String,
+    /// Partition columns of the table, most significant first. Queries are
+    /// restricted to the matching partitions so Athena doesn't scan the whole bucket.
+    #[serde(default = "Partition::default_layout")]
+    pub partitions: Vec<Partition>,
+    /// Seconds a query may run before it is stopped
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_secs: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Partition {
+    /// Part of the UTC date of the logs in a chrono format, e.g. `day` with
+    /// `%Y/%m/%d`, `dt` with `%Y-%m-%d` or `year`, `month` and `day` with `%Y`, `%m`, `%d`
+    Date { column: String, format: String },
+    /// Fixed values every query is restricted to, e.g. the regions of interest
+    Values { column: String, values: Vec<String> },
+}
+
+impl Partition {
+    pub fn date(column: &str, format: &str) -> Self {
+        Partition::Date {
+            column: column.to_string(),
+            format: format.to_string(),
+        }
+    }
+
+    pub fn column(&self) -> &str {
+        match self {
+            Partition::Date { column, .. } | Partition::Values { column, .. } => column,
+        }
+    }
+
+    /// A string partition `day` formatted `%Y/%m/%d`
+    pub fn default_layout() -> Vec<Partition> {
+        vec![Partition::date("day", "%Y/%m/%d")]
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -86,6 +128,8 @@ impl Default for DataSourceDetails {
             workgroup: "primary".to_string(),
             database: "default".to_string(),
             table: "<table>".to_string(),
+            partitions: Partition::default_layout(),
+            timeout_secs: None,
         })
     }
 }
@@ -94,19 +138,21 @@ impl Default for DataSourceDetails {
 pub enum ConfigError {
     FileNotFound,
     NoDatasourceFound,
+    Invalid(String),
+    Prompt(String),
+    Save(String),
 }
 
 impl fmt::Display for ConfigError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            ConfigError::FileNotFound => write!(
-                f,
-                "No configuration file found. Please use fivexx configure"
-            ),
-            ConfigError::NoDatasourceFound => write!(
-                f,
-                "No data sources found in the configuration. Please use fivexx configure"
-            ),
+            ConfigError::FileNotFound => write!(f, "No configuration file found"),
+            ConfigError::NoDatasourceFound => {
+                write!(f, "No data sources found in the configuration")
+            }
+            ConfigError::Invalid(msg) => write!(f, "Failed to read the configuration: {}", msg),
+            ConfigError::Prompt(msg) => write!(f, "Configuration aborted: {}", msg),
+            ConfigError::Save(msg) => write!(f, "Failed to save the configuration: {}", msg),
         }
     }
 }
@@ -117,12 +163,18 @@ impl std::error::Error for ConfigError {}
 pub struct Config {
     pub data_sources: Vec<DataSource>,
     pub default_domain: Option<String>,
+    /// IANA time zone name, e.g. Europe/Berlin. Used for dates without an offset and
+    /// to display timestamps. Defaults to UTC.
+    pub timezone: Option<String>,
+    /// USD per TB scanned by Athena, used to estimate the cost of queries. Defaults
+    /// to the on-demand price of 5 USD.
+    pub athena_price_per_tb: Option<f64>,
 }
 
 impl Config {
     pub fn load() -> Result<Self, ConfigError> {
-        let config =
-            confy::load::<Config>("fivexx", "config").map_err(|_| ConfigError::FileNotFound)?;
+        let config = confy::load::<Config>("fivexx", "config")
+            .map_err(|e| ConfigError::Invalid(e.to_string()))?;
 
         if config.data_sources.is_empty() {
             return Err(ConfigError::NoDatasourceFound);
@@ -137,10 +189,29 @@ impl Config {
             .map_err(|_| ConfigError::FileNotFound)
     }
 
+    /// Directory next to the config file for cached data, e.g. table schemas
+    pub fn cache_dir() -> Result<PathBuf, ConfigError> {
+        confy::get_configuration_file_path("fivexx", "config")
+            .ok()
+            .and_then(|path| path.parent().map(|dir| dir.join("cache")))
+            .ok_or(ConfigError::FileNotFound)
+    }
+
     pub fn default_domain(&self) -> Option<&String> {
         self.default_domain.as_ref()
     }
 
+    pub fn timezone(&self) -> Tz {
+        self.timezone
+            .as_deref()
+            .and_then(|timezone| timezone.parse().ok())
+            .unwrap_or(Tz::UTC)
+    }
+
+    pub fn athena_price_per_tb(&self) -> f64 {
+        self.athena_price_per_tb.unwrap_or(DEFAULT_PRICE_PER_TB)
+    }
+
     pub fn data_sources(&self) -> &Vec<DataSource> {
         &self.data_sources
     }
@@ -0,0 +1,471 @@
+use std::collections::{BTreeSet, HashMap};
+
+use chrono::{DateTime, Utc};
+use futures::{stream, StreamExt};
+use log::{debug, warn};
+use serde_json::{json, Value};
+
+use crate::{
+    adapters::{AdapterFactory, ExecutionOptions, NEW_RELIC_MAX_LIMIT},
+    column_mappings::get_mapping,
+    parallel_querier::{DataSourceError, DEFAULT_CONCURRENCY},
+    parsers::{Correlate, CorrelateCondition, QueryInput, Where},
+    query::{parse_timestamp, QueryOutput, QueryResult},
+    statistics::RunStatistics,
+};
+
+const DEFAULT_BATCH_SIZE: usize = 100;
+
+/// Rows a correlated query returns at most, the most NRQL allows
+const MAX_CORRELATED_ROWS: usize = NEW_RELIC_MAX_LIMIT;
+
+// TODO: use id of dataset instead
+const CORRELATED_COLUMN: &str = "correlated";
+
+type Row = HashMap<String, Value>;
+
+/// Value of a parent row for one dependent condition
+#[derive(Debug, Clone)]
+enum JoinKey {
+    Is(String),
+    Within(DateTime<Utc>),
+}
+
+/// Attaches the rows of the correlated data source to each parent row. Parent rows
+/// are processed in batches, so there is one correlated query per batch: `IS`
+/// conditions become an `IN (...)` over the batch and `WITHIN` conditions a time
+/// window covering all parents. The returned rows are joined back onto the parents
+/// in memory. Each query returns at most `MAX_CORRELATED_ROWS` rows and up to
+/// `concurrency` batches are queried at the same time.
+pub struct Correlator<'a> {
+    correlate: &'a Correlate,
+    adapter_factory: AdapterFactory,
+    batch_size: usize,
+    concurrency: usize,
+}
+
+impl<'a> Correlator<'a> {
+    pub fn new(correlate: &'a Correlate) -> Self {
+        Correlator {
+            correlate,
+            adapter_factory: AdapterFactory::new(),
+            batch_size: DEFAULT_BATCH_SIZE,
+            concurrency: DEFAULT_CONCURRENCY,
+        }
+    }
+
+    /// Maximum number of correlated queries in flight at the same time
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    pub fn options(mut self, options: ExecutionOptions) -> Self {
+        self.adapter_factory = self.adapter_factory.options(options);
+        self
+    }
+
+    /// Sets the `correlated` key of every row. Rows of a failed batch get no key.
+    /// Returns the errors of the failed batches and the statistics of the queries.
+    pub async fn correlate(&self, rows: &mut QueryResult) -> (Vec<DataSourceError>, RunStatistics) {
+        let mut errors = Vec::new();
+        let mut statistics = RunStatistics::default();
+
+        let keys = rows
+            .iter()
+            .map(|row| self.join_keys(row))
+            .collect::<Vec<_>>();
+
+        let outputs = stream::iter(keys.chunks(self.batch_size))
+            .map(|keys| self.query_batch(keys))
+            .buffered(self.concurrency)
+            .collect::<Vec<_>>()
+            .await;
+
+        let batches = rows
+            .chunks_mut(self.batch_size)
+            .zip(keys.chunks(self.batch_size));
+
+        for ((batch, keys), output) in batches.zip(outputs) {
+            match output {
+                Ok(output) => {
+                    if let Some(query_statistics) = output.statistics {
+                        statistics.add(&self.correlate.data_source.id, query_statistics);
+                    }
+
+                    self.join(batch, keys, &output.rows);
+                }
+                Err(e) => errors.push(e),
+            }
+        }
+
+        (errors, statistics)
+    }
+
+    /// The correlated rows of a batch, none if no row of the batch can be correlated
+    async fn query_batch(
+        &self,
+        keys: &[Option<Vec<JoinKey>>],
+    ) -> Result<QueryOutput, DataSourceError> {
+        let Some(query_input) = self.query_input(keys) else {
+            return Ok(QueryOutput::default());
+        };
+
+        let output = self.execute_query(&query_input).await?;
+
+        let limit = query_input.limit.unwrap_or(MAX_CORRELATED_ROWS);
+
+        if output.truncated || output.rows.len() >= limit {
+            warn!(
+                "[{}] Correlated query stopped after {} rows, some rows may lack correlated rows",
+                self.correlate.data_source.id,
+                output.rows.len()
+            );
+        }
+
+        Ok(output)
+    }
+
+    /// Sets the `correlated` key of every row of a batch to the children it matches
+    fn join(&self, batch: &mut [Row], keys: &[Option<Vec<JoinKey>>], children: &[Row]) {
+        for (row, keys) in batch.iter_mut().zip(keys) {
+            let matches = match keys {
+                Some(keys) => children
+                    .iter()
+                    .filter(|child| self.matches(child, keys))
+                    .collect::<Vec<_>>(),
+                None => vec![],
+            };
+
+            row.insert(CORRELATED_COLUMN.to_string(), json!(matches));
+        }
+    }
+
+    /// The correlated query without the IS and WITHIN conditions, which are only
+    /// known per batch, together with a description of them
+    pub async fn template(&self) -> Result<(String, String), DataSourceError> {
+        let data_source = &self.correlate.data_source;
+        let query_input = self.base_query_input();
+        let adapter = self.adapter_factory.create_adapter(data_source);
+
+        let query = adapter
+            .build_query(&query_input)
+            .await
+            .map_err(|e| DataSourceError::Build(data_source.id.clone(), e))?;
+
+        let conditions = self
+            .correlate
+            .dependent_conditions
+            .iter()
+            .map(|condition| match condition {
+                CorrelateCondition::Is { parent, child } => {
+                    format!("{} IN (<{} of the rows>)", child, parent)
+                }
+                CorrelateCondition::Within {
+                    parent,
+                    child,
+                    delta,
+                } => format!(
+                    "{} WITHIN {}s OF <{} of the rows>",
+                    child,
+                    delta.num_seconds(),
+                    parent
+                ),
+            })
+            .collect::<Vec<String>>()
+            .join(" AND ");
+
+        let description = format!("per batch of {} rows with {}", self.batch_size, conditions);
+
+        Ok((query, description))
+    }
+
+    async fn execute_query(
+        &self,
+        query_input: &QueryInput,
+    ) -> Result<QueryOutput, DataSourceError> {
+        let data_source = &self.correlate.data_source;
+        let adapter = self.adapter_factory.create_adapter(data_source);
+
+        let query = adapter
+            .build_query(query_input)
+            .await
+            .map_err(|e| DataSourceError::Build(data_source.id.clone(), e))?;
+
+        debug!("[{}] Correlated query: {}", data_source.id, query);
+
+        adapter
+            .execute_query(&query)
+            .await
+            .map_err(|e| DataSourceError::Execution(data_source.id.clone(), e))
+    }
+
+    /// One key per dependent condition, or `None` if the row lacks a parent column
+    /// and can therefore not be correlated.
+    fn join_keys(&self, row: &Row) -> Option<Vec<JoinKey>> {
+        self.correlate
+            .dependent_conditions
+            .iter()
+            .map(|condition| match condition {
+                CorrelateCondition::Is { parent, child } => {
+                    let value = value_to_string(row.get(parent)?)?;
+
+                    let mapped = row
+                        .get("data_source_id")
+                        .and_then(Value::as_str)
+                        .and_then(|id| {
+                            get_mapping(id, &self.correlate.data_source.id, parent, child)
+                        })
+                        .and_then(|mapping| mapping.get(&value))
+                        .cloned();
+
+                    Some(JoinKey::Is(mapped.unwrap_or(value)))
+                }
+                CorrelateCondition::Within { parent, .. } => {
+                    parse_timestamp(row.get(parent)?).map(JoinKey::Within)
+                }
+            })
+            .collect()
+    }
+
+    /// The correlated query as parsed, limited to `MAX_CORRELATED_ROWS` rows
+    fn base_query_input(&self) -> QueryInput {
+        let query_input = *self.correlate.query_input.clone();
+
+        QueryInput {
+            limit: query_input
+                .limit
+                .map(|limit| limit.min(MAX_CORRELATED_ROWS))
+                .or(Some(MAX_CORRELATED_ROWS)),
+            ..query_input
+        }
+    }
+
+    /// The correlated query for a batch, or `None` if no row of the batch can be correlated
+    fn query_input(&self, keys: &[Option<Vec<JoinKey>>]) -> Option<QueryInput> {
+        let keys = keys.iter().flatten().collect::<Vec<_>>();
+
+        if keys.is_empty() {
+            return None;
+        }
+
+        let mut query_input = self.base_query_input();
+
+        for (index, condition) in self.correlate.dependent_conditions.iter().enumerate() {
+            match condition {
+                CorrelateCondition::Is { child, .. } => {
+                    let values = keys
+                        .iter()
+                        .filter_map(|keys| match &keys[index] {
+                            JoinKey::Is(value) => Some(value.clone()),
+                            JoinKey::Within(_) => None,
+                        })
+                        .collect::<BTreeSet<String>>();
+
+                    query_input
+                        .conditions
+                        .push(Where::In(child.clone(), values.into_iter().collect()).into());
+                }
+                CorrelateCondition::Within { delta, .. } => {
+                    let times = keys.iter().filter_map(|keys| match keys[index] {
+                        JoinKey::Within(time) => Some(time),
+                        JoinKey::Is(_) => None,
+                    });
+
+                    let (min, max) = times.fold((None, None), |(min, max), time| {
+                        (
+                            Some(min.map_or(time, |min: DateTime<Utc>| min.min(time))),
+                            Some(max.map_or(time, |max: DateTime<Utc>| max.max(time))),
+                        )
+                    });
+
+                    query_input.since = min.map(|min| (min - *delta).fixed_offset());
+                    query_input.until = max.map(|max| (max + *delta).fixed_offset());
+                }
+            }
+        }
+
+        Some(query_input)
+    }
+
+    fn matches(&self, child: &Row, keys: &[JoinKey]) -> bool {
+        self.correlate
+            .dependent_conditions
+            .iter()
+            .zip(keys)
+            .all(|(condition, key)| match (condition, key) {
+                (CorrelateCondition::Is { child: column, .. }, JoinKey::Is(value)) => child
+                    .get(column)
+                    .and_then(value_to_string)
+                    .is_some_and(|child_value| &child_value == value),
+                (
+                    CorrelateCondition::Within {
+                        child: column,
+                        delta,
+                        ..
+                    },
+                    JoinKey::Within(time),
+                ) => child
+                    .get(column)
+                    .and_then(parse_timestamp)
+                    .is_some_and(|child_time| (child_time - *time).abs() <= *delta),
+                _ => false,
+            })
+    }
+}
+
+fn value_to_string(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        Value::Bool(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeDelta;
+
+    use crate::config::DataSource;
+
+    use super::*;
+
+    fn correlate() -> Correlate {
+        Correlate {
+            data_source: DataSource {
+                id: "nr".to_string(),
+                ..DataSource::default()
+            },
+            query_input: Box::default(),
+            dependent_conditions: vec![
+                CorrelateCondition::Is {
+                    parent: "client_ip".to_string(),
+                    child: "clientip".to_string(),
+                },
+                CorrelateCondition::Within {
+                    parent: "time".to_string(),
+                    child: "timestamp".to_string(),
+                    delta: TimeDelta::minutes(5),
+                },
+            ],
+        }
+    }
+
+    fn row(values: &[(&str, Value)]) -> Row {
+        values
+            .iter()
+            .map(|(column, value)| (column.to_string(), value.clone()))
+            .collect()
+    }
+
+    /// 2024-01-01T10:00:00Z plus `minutes` in epoch milliseconds, as New Relic returns it
+    fn millis(minutes: i64) -> Value {
+        json!(1_704_103_200_000i64 + minutes * 60_000)
+    }
+
+    fn correlated(row: &Row) -> Vec<Value> {
+        row[CORRELATED_COLUMN].as_array().unwrap().clone()
+    }
+
+    #[test]
+    fn joins_children_matching_all_keys() {
+        let correlate = correlate();
+        let correlator = Correlator::new(&correlate);
+
+        let mut rows = vec![
+            row(&[
+                ("client_ip", json!("1.1.1.1")),
+                ("time", json!("2024-01-01T10:00:00.000Z")),
+            ]),
+            row(&[
+                ("client_ip", json!("2.2.2.2")),
+                ("time", json!("2024-01-01T10:00:00.000Z")),
+            ]),
+        ];
+
+        let children = vec![
+            row(&[("clientip", json!("1.1.1.1")), ("timestamp", millis(4))]),
+            row(&[("clientip", json!("1.1.1.1")), ("timestamp", millis(-5))]),
+            // Outside of the window
+            row(&[("clientip", json!("1.1.1.1")), ("timestamp", millis(6))]),
+            // Other client
+            row(&[("clientip", json!("3.3.3.3")), ("timestamp", millis(0))]),
+        ];
+
+        let keys = rows
+            .iter()
+            .map(|row| correlator.join_keys(row))
+            .collect::<Vec<_>>();
+
+        correlator.join(&mut rows, &keys, &children);
+
+        assert_eq!(
+            correlated(&rows[0]),
+            vec![json!(children[0]), json!(children[1])]
+        );
+        assert!(correlated(&rows[1]).is_empty());
+    }
+
+    #[test]
+    fn rows_without_parent_columns_get_no_children() {
+        let correlate = correlate();
+        let correlator = Correlator::new(&correlate);
+
+        let mut rows = vec![row(&[("client_ip", json!("1.1.1.1"))])];
+        let children = vec![row(&[
+            ("clientip", json!("1.1.1.1")),
+            ("timestamp", millis(0)),
+        ])];
+
+        let keys = vec![correlator.join_keys(&rows[0])];
+
+        assert!(keys[0].is_none());
+
+        correlator.join(&mut rows, &keys, &children);
+
+        assert!(correlated(&rows[0]).is_empty());
+    }
+
+    #[test]
+    fn batch_query_covers_the_keys_of_the_batch() {
+        let correlate = correlate();
+        let correlator = Correlator::new(&correlate);
+
+        let rows = [
+            row(&[
+                ("client_ip", json!("2.2.2.2")),
+                ("time", json!("2024-01-01T10:00:00.000Z")),
+            ]),
+            row(&[
+                ("client_ip", json!("1.1.1.1")),
+                ("time", json!("2024-01-01T11:00:00.000Z")),
+            ]),
+        ];
+
+        let keys = rows
+            .iter()
+            .map(|row| correlator.join_keys(row))
+            .collect::<Vec<_>>();
+
+        let query_input = correlator.query_input(&keys).unwrap();
+
+        assert_eq!(
+            query_input.conditions,
+            vec![Where::In(
+                "clientip".to_string(),
+                vec!["1.1.1.1".to_string(), "2.2.2.2".to_string()]
+            )
+            .into()]
+        );
+        assert_eq!(
+            query_input.since.unwrap().to_rfc3339(),
+            "2024-01-01T09:55:00+00:00"
+        );
+        assert_eq!(
+            query_input.until.unwrap().to_rfc3339(),
+            "2024-01-01T11:05:00+00:00"
+        );
+        assert_eq!(query_input.limit, Some(MAX_CORRELATED_ROWS));
+    }
+}
@@ -0,0 +1,195 @@
+use std::fmt;
+
+use log::error;
+
+use crate::{
+    config::{ConfigError, CONFIG},
+    parallel_querier::DataSourceError,
+    parsers::QueryParserError,
+    query::{QueryError, QueryExecutionError},
+};
+
+/// Everything a command can fail with. The exit codes are part of the interface:
+///
+/// | code | failure |
+/// |------|---------|
+/// | 2    | invalid arguments, reported by clap, or unknown data source ids |
+/// | 3    | configuration |
+/// | 4    | query syntax |
+/// | 5    | a query could not be built for a data source |
+/// | 6    | a query failed on a data source |
+/// | 7    | writing the results |
+/// | 130  | interrupted |
+#[derive(Debug)]
+pub enum Error {
+    Config(ConfigError),
+    /// A raw query that doesn't parse, together with the query
+    Parse {
+        query: String,
+        error: QueryParserError,
+    },
+    /// Data sources that failed, the results of the others are still written
+    DataSources(Vec<DataSourceError>),
+    Output(String),
+    /// A data source id given as an argument that isn't configured
+    UnknownDataSource(String),
+}
+
+impl Error {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Config(_) => 3,
+            Error::Parse { .. } => 4,
+            Error::DataSources(errors)
+                if errors
+                    .iter()
+                    .any(|e| matches!(e, DataSourceError::Build(..))) =>
+            {
+                5
+            }
+            Error::DataSources(_) => 6,
+            Error::Output(_) => 7,
+            Error::UnknownDataSource(_) => 2,
+        }
+    }
+
+    /// Logs the error, every failed data source on its own line, with hints
+    pub fn report(&self) {
+        match self {
+            Error::DataSources(errors) => {
+                for e in errors {
+                    error!("Error: {}", e);
+
+                    if let Some(hint) = data_source_hint(e) {
+                        error!("  hint: {}", hint);
+                    }
+                }
+            }
+            e => {
+                error!("Error: {}", e);
+
+                if let Some(hint) = e.hint() {
+                    error!("  hint: {}", hint);
+                }
+            }
+        }
+    }
+
+    fn hint(&self) -> Option<String> {
+        match self {
+            Error::Config(_) => Some("run `fivexx configure` to set up data sources".to_string()),
+            Error::Parse {
+                error: QueryParserError::UnknownDataSource(id, _),
+                ..
+            }
+            | Error::UnknownDataSource(id) => did_you_mean(
+                id,
+                CONFIG
+                    .data_sources()
+                    .iter()
+                    .map(|data_source| data_source.id.as_str()),
+            )
+            .map(|id| format!("did you mean `{}`?", id)),
+            Error::Parse { .. } => None,
+            Error::DataSources(errors) => errors.first().and_then(data_source_hint),
+            Error::Output(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Config(e) => write!(f, "{}", e),
+            Error::Parse { query, error } => {
+                write!(f, "{}", error)?;
+
+                // The offending line of the query with the location underlined
+                let location = error.location();
+
+                if let Some(line) = query.lines().nth(location.line.saturating_sub(1)) {
+                    let width = query
+                        .get(location.start..location.end)
+                        .map_or(1, |token| token.chars().count().max(1));
+
+                    write!(
+                        f,
+                        "\n  {}\n  {}{}",
+                        line,
+                        " ".repeat(location.column.saturating_sub(1)),
+                        "^".repeat(width)
+                    )?;
+                }
+
+                Ok(())
+            }
+            Error::DataSources(errors) => {
+                let errors = errors.iter().map(|e| e.to_string()).collect::<Vec<_>>();
+
+                write!(f, "{}", errors.join("\n"))
+            }
+            Error::Output(msg) => write!(f, "Failed to write the results: {}", msg),
+            Error::UnknownDataSource(id) => write!(f, "Unknown data source '{}'", id),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<ConfigError> for Error {
+    fn from(e: ConfigError) -> Self {
+        Error::Config(e)
+    }
+}
+
+fn data_source_hint(e: &DataSourceError) -> Option<String> {
+    match e {
+        DataSourceError::Build(_, QueryError::UnknownColumn(_, Some(suggestion))) => {
+            Some(format!("did you mean `{}`?", suggestion))
+        }
+        DataSourceError::Build(id, QueryError::UnknownColumn(_, None)) => Some(format!(
+            "see `fivexx schema {}` for the columns of the data source",
+            id
+        )),
+        DataSourceError::Build(..) => None,
+        DataSourceError::Execution(_, QueryExecutionError::QueryTimeout(_)) => {
+            Some("raise --timeout to wait longer".to_string())
+        }
+        DataSourceError::Execution(_, QueryExecutionError::ClientError(_)) => {
+            Some("check the network access and credentials for the data source".to_string())
+        }
+        DataSourceError::Execution(..) => None,
+    }
+}
+
+/// The candidate closest to `name`, if it is close enough to be a typo
+pub fn did_you_mean<'a>(name: &str, candidates: impl Iterator<Item = &'a str>) -> Option<String> {
+    let name = name.to_lowercase();
+    let max_distance = (name.chars().count() / 3).max(1);
+
+    candidates
+        .map(|candidate| (distance(&name, &candidate.to_lowercase()), candidate))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate.to_string())
+}
+
+/// Levenshtein distance
+fn distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<char>>();
+    let mut previous = (0..=b.len()).collect::<Vec<usize>>();
+
+    for (i, a) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+
+        for (j, b) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a != *b);
+
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+
+        previous = current;
+    }
+
+    previous[b.len()]
+}
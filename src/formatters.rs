@@ -1,13 +1,118 @@
 mod csv_formatter;
 mod json_formatter;
+mod ndjson_formatter;
+mod table_formatter;
+
+use std::{collections::BTreeSet, error::Error};
 
 pub use csv_formatter::CSVFormatter;
 pub use json_formatter::JSONFormatter;
+pub use ndjson_formatter::NDJSONFormatter;
+pub use table_formatter::TableFormatter;
+
+use chrono::SecondsFormat;
+use chrono_tz::Tz;
+use serde_json::Value;
+
+use crate::query::{parse_timestamp, QueryResult};
 
-use crate::query::QueryResult;
+const LEADING_COLUMN: &str = "data_source_id";
+const CORRELATED_COLUMN: &str = "correlated";
 
 pub trait Formatter {
     type Output;
 
     fn format(&self, data: QueryResult) -> Self::Output;
 }
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    #[default]
+    Json,
+    Ndjson,
+    Csv,
+    Tsv,
+    Table,
+}
+
+impl OutputFormat {
+    /// Timestamps are rendered in `timezone`
+    pub fn format(&self, data: QueryResult, timezone: Tz) -> Result<String, Box<dyn Error>> {
+        self.format_with_metadata(data, timezone, None)
+    }
+
+    /// Like [`OutputFormat::format`], JSON nests the rows next to the `metadata`.
+    /// The other formats have no room for it.
+    pub fn format_with_metadata(
+        &self,
+        mut data: QueryResult,
+        timezone: Tz,
+        metadata: Option<Value>,
+    ) -> Result<String, Box<dyn Error>> {
+        if timezone != Tz::UTC {
+            localize_timestamps(&mut data, timezone);
+        }
+
+        match self {
+            OutputFormat::Json => Ok(JSONFormatter(metadata).format(data)),
+            OutputFormat::Ndjson => Ok(NDJSONFormatter().format(data)),
+            OutputFormat::Csv => CSVFormatter::default().format(data),
+            OutputFormat::Tsv => CSVFormatter::new('\t').format(data),
+            OutputFormat::Table => Ok(TableFormatter::default().format(data)),
+        }
+    }
+}
+
+/// Columns in a stable order: `data_source_id` first, then alphabetically.
+fn sorted_headers<'a>(keys: impl Iterator<Item = &'a String>) -> Vec<String> {
+    let columns = keys
+        .filter(|key| key.as_str() != CORRELATED_COLUMN)
+        .collect::<BTreeSet<&String>>();
+
+    let mut headers: Vec<String> = vec![];
+
+    if columns
+        .iter()
+        .any(|column| column.as_str() == LEADING_COLUMN)
+    {
+        headers.push(LEADING_COLUMN.to_string());
+    }
+
+    headers.extend(
+        columns
+            .into_iter()
+            .filter(|column| column.as_str() != LEADING_COLUMN)
+            .cloned(),
+    );
+
+    headers
+}
+
+/// Converts the UTC timestamp strings of all rows, including correlated rows, to
+/// RFC 3339 in `timezone`. Numbers are left alone, they are rarely timestamps.
+fn localize_timestamps(data: &mut QueryResult, timezone: Tz) {
+    for value in data.iter_mut().flat_map(|row| row.values_mut()) {
+        localize_value(value, timezone);
+    }
+}
+
+fn localize_value(value: &mut Value, timezone: Tz) {
+    match value {
+        Value::String(_) => {
+            if let Some(datetime) = parse_timestamp(value) {
+                *value = Value::String(
+                    datetime
+                        .with_timezone(&timezone)
+                        .to_rfc3339_opts(SecondsFormat::AutoSi, false),
+                );
+            }
+        }
+        Value::Array(values) => values
+            .iter_mut()
+            .for_each(|value| localize_value(value, timezone)),
+        Value::Object(row) => row
+            .values_mut()
+            .for_each(|value| localize_value(value, timezone)),
+        _ => {}
+    }
+}
@@ -1,8 +1,10 @@
-use std::error::Error;
+use std::{collections::HashMap, error::Error};
+
+use serde_json::{Map, Value};
 
 use crate::query::QueryResult;
 
-use super::Formatter;
+use super::{sorted_headers, Formatter, CORRELATED_COLUMN};
 
 const DEFAULT_DELIMITER: char = ',';
 
@@ -25,47 +27,97 @@ impl CSVFormatter {
     }
 
     fn escape_field(&self, field: &str) -> String {
-        if field.contains(self.delimiter) || field.contains('\n') || field.contains('"') {
+        if field.contains(self.delimiter)
+            || field.contains('\n')
+            || field.contains('\r')
+            || field.contains('"')
+        {
             format!("\"{}\"", field.replace('"', "\"\""))
         } else {
             field.to_string()
         }
     }
+
+    fn format_value(&self, value: Option<&Value>) -> String {
+        let field = match value {
+            None | Some(Value::Null) => String::new(),
+            Some(Value::String(s)) => s.clone(),
+            Some(value) => value.to_string(),
+        };
+
+        self.escape_field(&field)
+    }
+
+    fn format_row(&self, fields: Vec<String>) -> String {
+        let mut row = fields.join(&self.delimiter.to_string());
+        row.push('\n');
+        row
+    }
+}
+
+fn correlated_rows(row: &HashMap<String, Value>) -> Vec<&Map<String, Value>> {
+    match row.get(CORRELATED_COLUMN) {
+        Some(Value::Array(children)) => children.iter().filter_map(Value::as_object).collect(),
+        _ => vec![],
+    }
 }
 
 impl Formatter for CSVFormatter {
     type Output = Result<String, Box<dyn Error>>;
 
+    /// Correlated rows are joined onto their parent: each parent row is repeated once
+    /// per correlated row, with the correlated columns prefixed by `correlated.`.
     fn format(&self, data: QueryResult) -> Self::Output {
         let mut output = String::new();
 
-        Ok("".to_string())
-
-        // if data.is_empty() {
-        //     return Ok(output);
-        // }
-
-        // let first_row = &data[0];
-
-        // let headers: Vec<String> = first_row.keys().map(|key| key.to_string()).collect();
-
-        // output.push_str(&headers.join(&self.delimiter.to_string()));
-        // output.push('\n');
-
-        // for row in &data {
-        //     let formatted_row = headers
-        //         .iter()
-        //         .map(|header| {
-        //             let value = row.get(header).unwrap();
-        //             self.escape_field(value)
-        //         })
-        //         .collect::<Vec<String>>()
-        //         .join(&self.delimiter.to_string());
+        if data.is_empty() {
+            return Ok(output);
+        }
 
-        //     output.push_str(&formatted_row);
-        //     output.push('\n');
-        // }
+        let correlated = data.iter().map(correlated_rows).collect::<Vec<_>>();
+
+        let headers = sorted_headers(data.iter().flat_map(|row| row.keys()));
+        let correlated_headers =
+            sorted_headers(correlated.iter().flatten().flat_map(|row| row.keys()));
+
+        output.push_str(
+            &self.format_row(
+                headers
+                    .iter()
+                    .map(|header| self.escape_field(header))
+                    .chain(correlated_headers.iter().map(|header| {
+                        self.escape_field(&format!("{}.{}", CORRELATED_COLUMN, header))
+                    }))
+                    .collect(),
+            ),
+        );
+
+        for (row, children) in data.iter().zip(&correlated) {
+            let parent = headers
+                .iter()
+                .map(|header| self.format_value(row.get(header)))
+                .collect::<Vec<String>>();
+
+            if children.is_empty() {
+                let mut fields = parent;
+                fields.extend(correlated_headers.iter().map(|_| String::new()));
+
+                output.push_str(&self.format_row(fields));
+                continue;
+            }
+
+            for child in children {
+                let mut fields = parent.clone();
+                fields.extend(
+                    correlated_headers
+                        .iter()
+                        .map(|header| self.format_value(child.get(header))),
+                );
+
+                output.push_str(&self.format_row(fields));
+            }
+        }
 
-        // Ok(output)
+        Ok(output)
     }
 }
@@ -1,13 +1,58 @@
+use serde_json::{json, Value};
+
 use crate::query::QueryResult;
 
 use super::Formatter;
 
-pub struct JSONFormatter();
+/// An array of the rows, or an object with the rows under `rows` next to `metadata`
+pub struct JSONFormatter(pub Option<Value>);
 
 impl Formatter for JSONFormatter {
     type Output = String;
 
     fn format(&self, data: QueryResult) -> String {
-        serde_json::to_string(&data).unwrap()
+        match &self.0 {
+            Some(metadata) => json!({ "metadata": metadata, "rows": data }).to_string(),
+            None => serde_json::to_string(&data).unwrap(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn rows() -> QueryResult {
+        vec![HashMap::from([
+            ("elb_status_code".to_string(), json!(502)),
+            ("request_url".to_string(), json!("https://example.com/")),
+        ])]
+    }
+
+    #[test]
+    fn formats_rows_as_array() {
+        let formatted: Value = serde_json::from_str(&JSONFormatter(None).format(rows())).unwrap();
+
+        assert_eq!(
+            formatted,
+            json!([{ "elb_status_code": 502, "request_url": "https://example.com/" }])
+        );
+    }
+
+    #[test]
+    fn formats_rows_next_to_metadata() {
+        let metadata = json!({ "statistics": { "data_scanned_bytes": 1024 } });
+        let formatted: Value =
+            serde_json::from_str(&JSONFormatter(Some(metadata.clone())).format(rows())).unwrap();
+
+        assert_eq!(
+            formatted,
+            json!({
+                "metadata": metadata,
+                "rows": [{ "elb_status_code": 502, "request_url": "https://example.com/" }],
+            })
+        );
     }
 }
@@ -0,0 +1,16 @@
+use crate::query::QueryResult;
+
+use super::Formatter;
+
+/// One JSON object per line
+pub struct NDJSONFormatter();
+
+impl Formatter for NDJSONFormatter {
+    type Output = String;
+
+    fn format(&self, data: QueryResult) -> String {
+        data.iter()
+            .map(|row| serde_json::to_string(row).unwrap() + "\n")
+            .collect()
+    }
+}
@@ -0,0 +1,171 @@
+use std::io::IsTerminal;
+
+use serde_json::Value;
+
+use crate::query::QueryResult;
+
+use super::{sorted_headers, Formatter, CORRELATED_COLUMN};
+
+const COLUMN_SEPARATOR: &str = "  ";
+const ELLIPSIS: char = '…';
+
+/// Columns that are shortened first when the table is wider than the terminal
+const TRUNCATABLE_COLUMNS: [&str; 2] = ["request_url", "message"];
+const MIN_TRUNCATED_WIDTH: usize = 20;
+
+#[derive(Debug)]
+pub struct TableFormatter {
+    max_width: Option<usize>,
+}
+
+impl Default for TableFormatter {
+    /// Fits the table to the terminal width when stdout is a terminal
+    fn default() -> Self {
+        let max_width = if std::io::stdout().is_terminal() {
+            crossterm::terminal::size()
+                .ok()
+                .map(|(columns, _)| columns as usize)
+        } else {
+            None
+        };
+
+        TableFormatter { max_width }
+    }
+}
+
+impl TableFormatter {
+    fn format_value(value: Option<&Value>) -> String {
+        match value {
+            None | Some(Value::Null) => String::new(),
+            Some(Value::String(s)) => s.replace(['\n', '\r', '\t'], " "),
+            Some(Value::Array(rows)) => format!("{} rows", rows.len()),
+            Some(value) => value.to_string(),
+        }
+    }
+
+    /// Shrinks the truncatable columns until the table fits into `max_width`
+    fn fit_widths(&self, headers: &[String], widths: &mut [usize]) {
+        let Some(max_width) = self.max_width else {
+            return;
+        };
+
+        let separators = COLUMN_SEPARATOR.len() * headers.len().saturating_sub(1);
+
+        for column in TRUNCATABLE_COLUMNS {
+            let total = widths.iter().sum::<usize>() + separators;
+
+            if total <= max_width {
+                return;
+            }
+
+            if let Some(index) = headers.iter().position(|header| header == column) {
+                let excess = total - max_width;
+
+                widths[index] = widths[index]
+                    .saturating_sub(excess)
+                    .max(MIN_TRUNCATED_WIDTH.min(widths[index]));
+            }
+        }
+    }
+}
+
+fn truncate(value: &str, width: usize) -> String {
+    if value.chars().count() <= width {
+        return value.to_string();
+    }
+
+    let mut truncated = value
+        .chars()
+        .take(width.saturating_sub(1))
+        .collect::<String>();
+    truncated.push(ELLIPSIS);
+    truncated
+}
+
+fn pad(value: &str, width: usize, right_align: bool) -> String {
+    if right_align {
+        format!("{:>width$}", value, width = width)
+    } else {
+        format!("{:<width$}", value, width = width)
+    }
+}
+
+impl Formatter for TableFormatter {
+    type Output = String;
+
+    /// Aligned columns with `data_source_id` first and correlated rows shown as a count
+    fn format(&self, data: QueryResult) -> String {
+        let mut output = String::new();
+
+        if data.is_empty() {
+            return output;
+        }
+
+        let mut headers = sorted_headers(data.iter().flat_map(|row| row.keys()));
+
+        if data.iter().any(|row| row.contains_key(CORRELATED_COLUMN)) {
+            headers.push(CORRELATED_COLUMN.to_string());
+        }
+
+        let rows = data
+            .iter()
+            .map(|row| {
+                headers
+                    .iter()
+                    .map(|header| Self::format_value(row.get(header)))
+                    .collect::<Vec<String>>()
+            })
+            .collect::<Vec<_>>();
+
+        let right_align = headers
+            .iter()
+            .map(|header| {
+                data.iter()
+                    .filter_map(|row| row.get(header))
+                    .all(|value| value.is_number() || value.is_null())
+            })
+            .collect::<Vec<bool>>();
+
+        let mut widths = headers
+            .iter()
+            .enumerate()
+            .map(|(index, header)| {
+                rows.iter()
+                    .map(|row| row[index].chars().count())
+                    .chain(std::iter::once(header.chars().count()))
+                    .max()
+                    .unwrap_or_default()
+            })
+            .collect::<Vec<usize>>();
+
+        self.fit_widths(&headers, &mut widths);
+
+        let format_line = |cells: &[String], align: &dyn Fn(usize) -> bool| {
+            let line = cells
+                .iter()
+                .enumerate()
+                .map(|(index, cell)| {
+                    pad(&truncate(cell, widths[index]), widths[index], align(index))
+                })
+                .collect::<Vec<String>>()
+                .join(COLUMN_SEPARATOR);
+
+            format!("{}\n", line.trim_end())
+        };
+
+        output.push_str(&format_line(&headers, &|_| false));
+        output.push_str(&format_line(
+            &widths
+                .iter()
+                .map(|width| "-".repeat(*width))
+                .collect::<Vec<_>>(),
+            &|_| false,
+        ));
+
+        for row in &rows {
+            output.push_str(&format_line(row, &|index| right_align[index]));
+        }
+
+        output
+    }
+}
@@ -0,0 +1,52 @@
+use std::time::Instant;
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+/// Writes the records of fivexx to stderr, keeping stdout for the results. Debug
+/// and trace records are prefixed with the seconds since the start.
+struct Logger {
+    started: Instant,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level() && metadata.target().starts_with("fivexx")
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        match record.level() {
+            Level::Error | Level::Warn | Level::Info => eprintln!("{}", record.args()),
+            level => eprintln!(
+                "[{:.3}s {}] {}",
+                self.started.elapsed().as_secs_f64(),
+                level.as_str().to_lowercase(),
+                record.args()
+            ),
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// `quiet` only keeps errors, every `verbose` step adds a level: the queries and
+/// timings first, then the polls, pages and request ids
+pub fn init(verbose: u8, quiet: bool) {
+    let level = match (quiet, verbose) {
+        (true, _) => LevelFilter::Error,
+        (false, 0) => LevelFilter::Info,
+        (false, 1) => LevelFilter::Debug,
+        (false, _) => LevelFilter::Trace,
+    };
+
+    let logger = Logger {
+        started: Instant::now(),
+    };
+
+    if log::set_boxed_logger(Box::new(logger)).is_ok() {
+        log::set_max_level(level);
+    }
+}
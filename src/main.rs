@@ -1,44 +1,83 @@
-use clap::Parser;
-use commands::{configure, query, Commands};
+use clap::{ArgAction, Parser};
+use commands::{configure, query, schema, Commands};
+use error::Error;
+use log::warn;
+use running_queries::RUNNING_QUERIES;
 
 mod adapters;
 mod commands;
 mod config;
+mod correlator;
+mod error;
 mod formatters;
 mod column_mappings;
+mod logger;
+mod pager;
 mod parallel_querier;
 mod parsers;
+mod progress;
 mod query;
+mod running_queries;
+mod statistics;
+mod timeseries;
+
+const EXIT_CODES: &str = "Exit codes: 2 invalid arguments, 3 configuration, 4 query syntax, \
+5 query could not be built, 6 query failed, 7 writing results, 130 interrupted";
 
 #[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
+#[command(version, about, long_about = None, after_help = EXIT_CODES)]
 struct Cli {
     #[command(subcommand)]
     cmd: Commands,
+
+    /// Log more to stderr: -v for the queries and timings, -vv also for polls, pages and request ids
+    #[arg(short, long, action = ArgAction::Count, global = true)]
+    verbose: u8,
+
+    /// Same as -vv
+    #[arg(long, global = true)]
+    debug: bool,
+
+    /// Only log errors
+    #[arg(short, long, global = true, conflicts_with_all = ["verbose", "debug"])]
+    quiet: bool,
 }
 
 // TODO:
-//   - implement 5xx
-//   - change NaiveDateTime to UTC
-//   - implement --output format
-//   - improve QueryError
-//   - implement --debug mode (improve query logging)
-//   - implement count by hour, week
-//   - implement parallel execution
 //   - add dataset columns, etc. to output
-//   implement average by (potentially should have "aggregators", also want percentiles, etc.)
 // --aggregate-by=day, --aggreggation=  - aggregat/select e.g. --select="COUNT(*)" --group-by=day
 
 #[tokio::main]
 async fn main() {
     let args = Cli::parse();
 
-    match args.cmd {
-        Commands::Configure(args) => {
-            let _ = configure(args);
+    logger::init(if args.debug { 2 } else { args.verbose }, args.quiet);
+
+    let run = async {
+        match args.cmd {
+            Commands::Configure(args) => configure(args).map_err(Error::from),
+            Commands::Query(args) => query(*args).await,
+            Commands::Schema(args) => schema(*args).await,
         }
-        Commands::Query(args) => {
-            let _ = query(args).await;
+    };
+
+    // Pinned here, so the running queries are still registered when interrupted
+    let mut run = std::pin::pin!(run);
+
+    tokio::select! {
+        result = &mut run => {
+            if let Err(e) = result {
+                e.report();
+
+                std::process::exit(e.exit_code());
+            }
+        }
+        _ = tokio::signal::ctrl_c() => {
+            warn!("Interrupted, stopping running queries...");
+
+            RUNNING_QUERIES.stop_all().await;
+
+            std::process::exit(130);
         }
     }
 }
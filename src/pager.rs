@@ -0,0 +1,41 @@
+use std::{
+    io::{self, IsTerminal, Write},
+    process::{Command, Stdio},
+};
+
+const DEFAULT_PAGER: &str = "less -FRSX";
+
+/// Writes `content` to stdout, through `$PAGER` (or less) when stdout is a terminal.
+/// Falls back to writing directly if the pager cannot be started.
+pub fn page(content: &str) -> io::Result<()> {
+    if !io::stdout().is_terminal() {
+        return io::stdout().write_all(content.as_bytes());
+    }
+
+    let pager = std::env::var("PAGER").unwrap_or_else(|_| DEFAULT_PAGER.to_string());
+    let mut parts = pager.split_whitespace();
+
+    let Some(program) = parts.next() else {
+        return io::stdout().write_all(content.as_bytes());
+    };
+
+    let Ok(mut child) = Command::new(program)
+        .args(parts)
+        .stdin(Stdio::piped())
+        .spawn()
+    else {
+        return io::stdout().write_all(content.as_bytes());
+    };
+
+    if let Some(mut stdin) = child.stdin.take() {
+        // The pager closing early (e.g. quitting less) is not an error
+        match stdin.write_all(content.as_bytes()) {
+            Err(e) if e.kind() != io::ErrorKind::BrokenPipe => return Err(e),
+            _ => {}
+        }
+    }
+
+    child.wait()?;
+
+    Ok(())
+}
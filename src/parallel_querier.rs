@@ -1,73 +1,237 @@
-// use futures::future::join_all;
-// use std::{collections::HashMap, fs::File, io::Write};
-// use tokio::task;
-
-// use crate::{
-//     adapters::{AdapterFactory, QueryAdapter},
-//     config::DataSource,
-//     formatters::{CSVFormatter, Formatter},
-//     query::{QueryError, QueryInput, QueryResult},
-// };
-
-// pub struct ParallelQuerier {
-//     datasets: Vec<DataSource>,
-//     adapter_factory: AdapterFactory,
-// }
-
-// impl ParallelQuerier {
-//     pub fn new(datasets: Vec<DataSource>) -> Self {
-//         ParallelQuerier {
-//             datasets,
-//             adapter_factory: AdapterFactory::new(),
-//         }
-//     }
-
-//     pub async fn execute(&self, query_input: &QueryInput) -> QueryResult {
-//         let results = self.execute_parallel_queries(query_input).await;
-//         self.combine_results(results)
-//     }
-
-//     async fn execute_parallel_queries(
-//         &self,
-//         query_input: &QueryInput,
-//     ) -> Vec<Result<QueryResult, QueryError>> {
-//         let mut handles = Vec::new();
-
-//         for data_source in &self.datasets {
-//             let query_input = query_input.clone();
-//             let adapter = self.adapter_factory.create_adapter(data_source);
-//             let handle = task::spawn(async move {
-//                 let query = adapter.build_query(&query_input);
-//                 println!("Executing query for dataset {}: {}", data_source.id, query);
-
-//                 adapter.execute_query(&query).await
-//             });
-//             handles.push(handle);
-//         }
-
-//         join_all(handles)
-//             .await
-//             .into_iter()
-//             .map(|res| {
-//                 res.unwrap_or_else(|e| {
-//                     Err(QueryError {
-//                         message: format!("Task join error: {}", e),
-//                     })
-//                 })
-//             })
-//             .collect()
-//     }
-
-//     fn combine_results(&self, results: Vec<Result<QueryResult, QueryError>>) -> QueryResult {
-//         let mut combined_results = Vec::new();
-
-//         for result in results {
-//             match result {
-//                 Ok(mut query_result) => combined_results.append(&mut query_result),
-//                 Err(e) => eprintln!("Error executing query: {}", e),
-//             }
-//         }
-
-//         combined_results
-//     }
-// }
+use std::{cmp::Ordering, fmt, time::Instant};
+
+use futures::{stream, StreamExt};
+use log::debug;
+use serde_json::Value;
+
+use crate::{
+    adapters::{AdapterFactory, ExecutionOptions},
+    config::DataSource,
+    parsers::{Order, QueryInput},
+    query::{
+        compare_values, QueryError, QueryExecutionError, QueryPlan, QueryResult, QueryStatistics,
+    },
+    statistics::RunStatistics,
+    timeseries::zero_fill,
+};
+
+pub const DEFAULT_CONCURRENCY: usize = 4;
+
+#[derive(Debug)]
+pub enum DataSourceError {
+    Build(String, QueryError),
+    Execution(String, QueryExecutionError),
+}
+
+impl fmt::Display for DataSourceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DataSourceError::Build(id, e) => write!(f, "[{}] Failed to build query: {}", id, e),
+            DataSourceError::Execution(id, e) => {
+                write!(f, "[{}] Failed to execute query: {}", id, e)
+            }
+        }
+    }
+}
+
+impl std::error::Error for DataSourceError {}
+
+/// Runs the same query against several data sources concurrently. A failing data
+/// source does not affect the results of the others.
+pub struct ParallelQuerier<'a> {
+    data_sources: &'a [DataSource],
+    adapter_factory: AdapterFactory,
+    concurrency: usize,
+}
+
+impl<'a> ParallelQuerier<'a> {
+    pub fn new(data_sources: &'a [DataSource]) -> Self {
+        ParallelQuerier {
+            data_sources,
+            adapter_factory: AdapterFactory::new(),
+            concurrency: DEFAULT_CONCURRENCY,
+        }
+    }
+
+    /// Maximum number of queries in flight at the same time
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    pub fn options(mut self, options: ExecutionOptions) -> Self {
+        self.adapter_factory = self.adapter_factory.options(options);
+        self
+    }
+
+    /// Returns the combined rows, tagged with `data_source_id`, in data source order
+    /// (by bucket for TIMESERIES, see [`zero_fill`]) together with the errors of the
+    /// data sources that failed and the statistics of the queries.
+    pub async fn execute(
+        &self,
+        query_input: &'a QueryInput,
+    ) -> (QueryResult, Vec<DataSourceError>, RunStatistics) {
+        let results = stream::iter(self.data_sources)
+            .map(|data_source| self.execute_query(data_source, query_input))
+            .buffered(self.concurrency)
+            .collect::<Vec<_>>()
+            .await;
+
+        let (mut combined_results, errors, statistics) = self.combine_results(results);
+
+        let data_source_ids = self
+            .data_sources
+            .iter()
+            .map(|data_source| data_source.id.clone())
+            .collect::<Vec<String>>();
+
+        // Zero rows take part in the global ORDER BY and LIMIT like any other row
+        zero_fill(&mut combined_results, query_input, &data_source_ids);
+
+        Self::order_and_limit(&mut combined_results, query_input);
+
+        (combined_results, errors, statistics)
+    }
+
+    /// The query of every data source in data source order, without running them
+    pub async fn build_queries(
+        &self,
+        query_input: &'a QueryInput,
+    ) -> Vec<Result<(String, String), DataSourceError>> {
+        stream::iter(self.data_sources)
+            .then(|data_source| async move {
+                let adapter = self.adapter_factory.create_adapter(data_source);
+
+                adapter
+                    .build_query(query_input)
+                    .await
+                    .map(|query| (data_source.id.clone(), query))
+                    .map_err(|e| DataSourceError::Build(data_source.id.clone(), e))
+            })
+            .collect()
+            .await
+    }
+
+    /// The plan of the query of every data source in data source order, see
+    /// [`QueryAdapter::explain`](crate::adapters::QueryAdapter::explain)
+    pub async fn explain(
+        &self,
+        query_input: &'a QueryInput,
+        analyze: bool,
+    ) -> Vec<Result<(String, QueryPlan), DataSourceError>> {
+        stream::iter(self.data_sources)
+            .map(|data_source| async move {
+                let adapter = self.adapter_factory.create_adapter(data_source);
+
+                let query = adapter
+                    .build_query(query_input)
+                    .await
+                    .map_err(|e| DataSourceError::Build(data_source.id.clone(), e))?;
+
+                adapter
+                    .explain(&query, analyze)
+                    .await
+                    .map(|plan| (data_source.id.clone(), plan))
+                    .map_err(|e| DataSourceError::Execution(data_source.id.clone(), e))
+            })
+            .buffered(self.concurrency)
+            .collect()
+            .await
+    }
+
+    /// Every source only ordered and limited its own rows, so ORDER BY and LIMIT are
+    /// applied again to the combined rows. Missing values sort last.
+    fn order_and_limit(rows: &mut QueryResult, query_input: &QueryInput) {
+        if !query_input.order_by.is_empty() {
+            rows.sort_by(|a, b| {
+                query_input
+                    .order_by
+                    .iter()
+                    .map(|order_by| {
+                        let (a, b) = (a.get(&order_by.column), b.get(&order_by.column));
+
+                        match (
+                            a.is_some_and(|a| !a.is_null()),
+                            b.is_some_and(|b| !b.is_null()),
+                        ) {
+                            (true, true) if order_by.order == Order::Desc => compare_values(b, a),
+                            (true, true) => compare_values(a, b),
+                            (a, b) => b.cmp(&a),
+                        }
+                    })
+                    .find(|ordering| ordering.is_ne())
+                    .unwrap_or(Ordering::Equal)
+            });
+        }
+
+        if let Some(limit) = query_input.limit {
+            rows.truncate(limit);
+        }
+    }
+
+    async fn execute_query(
+        &self,
+        data_source: &'a DataSource,
+        query_input: &'a QueryInput,
+    ) -> Result<(QueryResult, Option<QueryStatistics>), DataSourceError> {
+        let adapter = self.adapter_factory.create_adapter(data_source);
+
+        let query = adapter
+            .build_query(query_input)
+            .await
+            .map_err(|e| DataSourceError::Build(data_source.id.clone(), e))?;
+
+        debug!("[{}] Query: {}", data_source.id, query);
+
+        let started = Instant::now();
+
+        let output = adapter
+            .execute_query(&query)
+            .await
+            .map_err(|e| DataSourceError::Execution(data_source.id.clone(), e))?;
+
+        debug!(
+            "[{}] {} rows in {:.1}s",
+            data_source.id,
+            output.rows.len(),
+            started.elapsed().as_secs_f64()
+        );
+
+        let mut result = output.rows;
+
+        adapter.post_process(query_input, &mut result);
+
+        for row in &mut result {
+            row.insert(
+                "data_source_id".to_string(),
+                Value::String(data_source.id.clone()),
+            );
+        }
+
+        Ok((result, output.statistics))
+    }
+
+    fn combine_results(
+        &self,
+        results: Vec<Result<(QueryResult, Option<QueryStatistics>), DataSourceError>>,
+    ) -> (QueryResult, Vec<DataSourceError>, RunStatistics) {
+        let mut combined_results = Vec::new();
+        let mut errors = Vec::new();
+        let mut statistics = RunStatistics::default();
+
+        for (data_source, result) in self.data_sources.iter().zip(results) {
+            match result {
+                Ok((mut query_result, query_statistics)) => {
+                    combined_results.append(&mut query_result);
+
+                    if let Some(query_statistics) = query_statistics {
+                        statistics.add(&data_source.id, query_statistics);
+                    }
+                }
+                Err(e) => errors.push(e),
+            }
+        }
+
+        (combined_results, errors, statistics)
+    }
+}
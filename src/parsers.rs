@@ -1,14 +1,18 @@
+mod bucket_parser;
 mod dataset_parser;
 mod date_time_parser;
 mod domain_parser;
 mod duration_parser;
 mod query_parser;
+mod status_code_parser;
 
+pub use crate::parsers::bucket_parser::BucketParser;
 pub use crate::parsers::dataset_parser::DatasetParser;
 pub use crate::parsers::date_time_parser::DateTimeParser;
 pub use crate::parsers::domain_parser::DomainParser;
 pub use crate::parsers::duration_parser::DurationParser;
 pub use crate::parsers::query_parser::*;
+pub use crate::parsers::status_code_parser::StatusCodeParser;
 
 pub trait Parser {
     type Output;
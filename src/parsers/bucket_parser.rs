@@ -0,0 +1,31 @@
+use chrono::Duration;
+use std::ops::Deref;
+
+use super::{DurationParser, Parser};
+
+/// Width of a time bucket, e.g. `5m`, `1h` or `1 day`. Has to be whole seconds
+/// because the adapters truncate timestamps to seconds.
+#[derive(Debug, Clone)]
+pub struct BucketParser(Duration);
+
+impl Deref for BucketParser {
+    type Target = Duration;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl Parser for BucketParser {
+    type Output = Duration;
+
+    fn from_str(input: &str) -> Result<Duration, &'static str> {
+        let bucket = DurationParser::from_str(input)?;
+
+        if bucket.num_seconds() < 1 || bucket.subsec_nanos() != 0 {
+            return Err("Bucket has to be a whole number of seconds");
+        }
+
+        Ok(bucket)
+    }
+}
@@ -1,6 +1,11 @@
 use std::ops::Deref;
 
-use crate::config::{DataSource, CONFIG};
+use log::trace;
+
+use crate::{
+    config::{Config, ConfigError, DataSource, CONFIG},
+    error::Error,
+};
 
 use super::Parser;
 
@@ -25,17 +30,20 @@ impl Parser for DatasetParser {
 
         // TODO: Config::data_sources().where_id_in(&dataset_ids)
 
-        println!("dataset_ids: {:?}", dataset_ids);
+        trace!("Data source ids: {:?}", dataset_ids);
 
         // TODO: not great that we are clongin
         let data_sources = CONFIG
             .data_sources()
-            .into_iter()
+            .iter()
             .filter(|ds| dataset_ids.contains(&ds.id))
-            .map(|ds| ds.clone())
+            .cloned()
             .collect::<Vec<DataSource>>();
 
-        println!("data_sources: {:?}", data_sources);
+        trace!(
+            "Data sources found: {:?}",
+            data_sources.iter().map(|ds| &ds.id).collect::<Vec<_>>()
+        );
 
         // let data_sources = DataSource.where_id_in(&dataset_ids);
         Ok(data_sources)
@@ -43,11 +51,28 @@ impl Parser for DatasetParser {
 }
 
 impl DatasetParser {
+    /// Like [`DatasetParser::from_id`], failing with the reason the config can't be
+    /// loaded if it has no data sources
+    pub fn parse_id(id: &str) -> Result<DataSource, Error> {
+        Self::require_config()?;
+
+        Self::from_id(id).ok_or_else(|| Error::UnknownDataSource(id.to_string()))
+    }
+
+    /// Fails with the reason the config can't be loaded if it has no data sources
+    pub fn require_config() -> Result<(), Error> {
+        if CONFIG.data_sources().is_empty() {
+            return Err(Error::Config(
+                Config::load()
+                    .err()
+                    .unwrap_or(ConfigError::NoDatasourceFound),
+            ));
+        }
+
+        Ok(())
+    }
+
     pub fn from_id(id: &str) -> Option<DataSource> {
-        CONFIG
-            .data_sources()
-            .into_iter()
-            .find(|ds| ds.id == id)
-            .cloned()
+        CONFIG.data_sources().iter().find(|ds| ds.id == id).cloned()
     }
 }
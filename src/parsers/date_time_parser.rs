@@ -1,14 +1,25 @@
-use chrono::{Duration, NaiveDate, NaiveDateTime, Utc};
+use chrono::{
+    DateTime, Datelike, Days, FixedOffset, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc,
+    Weekday,
+};
+use chrono_tz::Tz;
 use regex::Regex;
 use std::ops::Deref;
 
-use super::Parser;
+use crate::config::CONFIG;
 
+use super::{duration_parser::Span, Parser};
+
+/// Epoch values from this size on are milliseconds (1973-03-03 in seconds)
+const EPOCH_MILLIS_THRESHOLD: i64 = 100_000_000_000;
+
+/// Point in time with the offset it was given in. Inputs without an offset or time
+/// zone are interpreted in the configured default time zone.
 #[derive(Debug, Clone)]
-pub struct DateTimeParser(NaiveDateTime);
+pub struct DateTimeParser(DateTime<FixedOffset>);
 
 impl Deref for DateTimeParser {
-    type Target = NaiveDateTime;
+    type Target = DateTime<FixedOffset>;
 
     fn deref(&self) -> &Self::Target {
         &self.0
@@ -16,45 +27,267 @@ impl Deref for DateTimeParser {
 }
 
 impl Parser for DateTimeParser {
-    type Output = NaiveDateTime;
+    type Output = DateTime<FixedOffset>;
+
+    fn from_str(input: &str) -> Result<DateTime<FixedOffset>, &'static str> {
+        Self::parse_at(input, Utc::now(), CONFIG.timezone())
+    }
+}
+
+impl DateTimeParser {
+    /// Parses `input` relative to `now` in `timezone`. Accepts
+    /// - "now", epoch seconds or milliseconds
+    /// - spans like "30m", "1.5 hours ago" or "2 months" (see [`Span::parse`])
+    /// - "YYYY-MM-DD", "YYYY-MM-DD HH[:MM[:SS[.fff]]]" and RFC 3339
+    /// - "today", "yesterday" or "[last] monday", optionally followed by "HH[:MM[:SS]]"
+    ///
+    /// Absolute and calendar inputs may end with an offset or IANA time zone name,
+    /// e.g. "yesterday 09:00 Europe/Berlin" or "2022-01-01 17:00 +02:00".
+    pub fn parse_at(
+        input: &str,
+        now: DateTime<Utc>,
+        timezone: Tz,
+    ) -> Result<DateTime<FixedOffset>, &'static str> {
+        let input = input.trim();
 
-    fn from_str(input: &str) -> Result<NaiveDateTime, &'static str> {
-        // Try parsing as "YYYY-MM-DD"
-        if let Ok(date) = NaiveDate::parse_from_str(input, "%Y-%m-%d") {
-            let datetime = date.and_hms_opt(0, 0, 0).unwrap();
+        if input.eq_ignore_ascii_case("now") {
+            return Ok(now.fixed_offset());
+        }
 
-            return Ok(datetime);
+        if let Some(datetime) = parse_epoch(input) {
+            return datetime.ok_or("Epoch timestamp out of range");
         }
 
-        // Try parsing as "YYYY-MM-DD HH:MM:SS"
-        let formats = ["%Y-%m-%d %H", "%Y-%m-%d %H:%M", "%Y-%m-%d %H:%M:%S"];
+        if let Some(span) = Span::parse(input) {
+            return span
+                .before(now)
+                .map(|datetime| datetime.fixed_offset())
+                .ok_or("Date time out of range");
+        }
 
-        for format in formats.iter() {
-            if let Ok(datetime) = NaiveDateTime::parse_from_str(input, format) {
-                return Ok(datetime);
+        // A trailing offset (Z, +02:00, -0500) or IANA zone name (Europe/Berlin, UTC)
+        let re = Regex::new(r"^(.+?)\s*(Z|[+-]\d{2}:?\d{2}|UTC|[A-Za-z]+(?:/[A-Za-z0-9_+-]+)+)$")
+            .unwrap();
+
+        match re.captures(input) {
+            Some(caps) => {
+                let datetime = caps.get(1).unwrap().as_str();
+
+                match &caps[2] {
+                    "Z" | "UTC" => resolve(&Utc, datetime, now),
+                    zone if zone.starts_with(['+', '-']) => {
+                        let offset = zone
+                            .parse::<FixedOffset>()
+                            .map_err(|_| "Could not parse time zone offset")?;
+
+                        resolve(&offset, datetime, now)
+                    }
+                    zone => {
+                        let tz = zone.parse::<Tz>().map_err(|_| "Unknown time zone")?;
+
+                        resolve(&tz, datetime, now)
+                    }
+                }
             }
+            None => resolve(&timezone, input, now),
         }
+    }
+}
+
+/// `None` if `input` is no epoch timestamp, i.e. not a number of at least 9 digits
+fn parse_epoch(input: &str) -> Option<Option<DateTime<FixedOffset>>> {
+    if input.len() < 9 || !input.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+
+    let Ok(epoch) = input.parse::<i64>() else {
+        return Some(None);
+    };
+
+    let datetime = if epoch >= EPOCH_MILLIS_THRESHOLD {
+        DateTime::from_timestamp_millis(epoch)
+    } else {
+        DateTime::from_timestamp(epoch, 0)
+    };
+
+    Some(datetime.map(|datetime| datetime.fixed_offset()))
+}
+
+/// Parses a local date time in `tz`, with calendar words relative to `now`
+fn resolve<T: TimeZone>(
+    tz: &T,
+    input: &str,
+    now: DateTime<Utc>,
+) -> Result<DateTime<FixedOffset>, &'static str> {
+    let today = now.with_timezone(tz).date_naive();
 
-        let re = Regex::new(r"(?i)^(\d+)\s*(day|week|hour|minute)s?\s*(ago)?$").unwrap();
+    let datetime = parse_absolute(input)
+        .or_else(|| parse_calendar(input, today))
+        .ok_or("Could not parse date time")?;
 
-        if let Some(caps) = re.captures(input) {
-            let amount: i64 = caps[1].parse().unwrap();
+    // The earlier one if the local time is ambiguous (DST end)
+    tz.from_local_datetime(&datetime)
+        .earliest()
+        .map(|datetime| datetime.fixed_offset())
+        .ok_or("Date time does not exist in the given time zone")
+}
 
-            let unit = &caps[2];
+/// "YYYY-MM-DD" or "YYYY-MM-DD[ T]HH[:MM[:SS[.fff]]]"
+fn parse_absolute(input: &str) -> Option<NaiveDateTime> {
+    if let Ok(date) = NaiveDate::parse_from_str(input, "%Y-%m-%d") {
+        return date.and_hms_opt(0, 0, 0);
+    }
 
-            let duration = match unit {
-                "day" => Duration::days(amount),
-                "week" => Duration::weeks(amount),
-                "hour" => Duration::hours(amount),
-                "minute" => Duration::minutes(amount),
-                _ => unreachable!(),
-            };
+    let input = input.replacen(['T', 't'], " ", 1);
 
-            let datetime = Utc::now().naive_utc() - duration;
+    let formats = ["%Y-%m-%d %H:%M", "%Y-%m-%d %H:%M:%S%.f"];
 
-            return Ok(datetime);
+    formats
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(&input, format).ok())
+        // chrono needs the minutes, so "YYYY-MM-DD HH" is completed to "YYYY-MM-DD HH:00"
+        .or_else(|| NaiveDateTime::parse_from_str(&format!("{}:00", input), formats[0]).ok())
+}
+
+/// "today", "yesterday", "monday" or "last monday" with an optional time of day
+fn parse_calendar(input: &str, today: NaiveDate) -> Option<NaiveDateTime> {
+    let re = Regex::new(
+        r"(?i)^(today|yesterday|(?:last\s+)?([a-z]+))(?:\s+(\d{1,2})(?::(\d{2}))?(?::(\d{2}))?)?$",
+    )
+    .unwrap();
+
+    let caps = re.captures(input)?;
+
+    let date = match caps[1].to_lowercase().as_str() {
+        "today" => today,
+        "yesterday" => today.pred_opt()?,
+        _ => {
+            let weekday = caps[2].parse::<Weekday>().ok()?;
+
+            // The most recent one before today
+            let days_back = (today.weekday().days_since(weekday) + 6) % 7 + 1;
+
+            today.checked_sub_days(Days::new(days_back as u64))?
         }
+    };
+
+    let number = |index: usize| {
+        caps.get(index)
+            .map_or(Some(0), |value| value.as_str().parse::<u32>().ok())
+    };
+
+    let time = NaiveTime::from_hms_opt(number(3)?, number(4)?, number(5)?)?;
+
+    Some(date.and_time(time))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Monday 2024-03-04 10:30 UTC
+    fn now() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 3, 4, 10, 30, 0).unwrap()
+    }
+
+    fn parse(input: &str) -> Result<DateTime<FixedOffset>, &'static str> {
+        DateTimeParser::parse_at(input, now(), Tz::UTC)
+    }
+
+    fn datetime(input: &str) -> DateTime<FixedOffset> {
+        DateTime::parse_from_rfc3339(input).unwrap()
+    }
+
+    #[test]
+    fn parses_now() {
+        assert_eq!(parse("now"), Ok(now().fixed_offset()));
+        assert_eq!(parse(" NOW "), Ok(now().fixed_offset()));
+    }
+
+    #[test]
+    fn parses_epoch_seconds_and_millis() {
+        assert_eq!(parse("100000000"), Ok(datetime("1973-03-03T09:46:40Z")));
+        assert_eq!(parse("1709548200"), Ok(datetime("2024-03-04T10:30:00Z")));
+        assert_eq!(
+            parse("1709548200123"),
+            Ok(datetime("2024-03-04T10:30:00.123Z"))
+        );
+    }
+
+    #[test]
+    fn parses_spans_before_now() {
+        assert_eq!(parse("30m"), Ok(datetime("2024-03-04T10:00:00Z")));
+        assert_eq!(parse("1.5 hours ago"), Ok(datetime("2024-03-04T09:00:00Z")));
+    }
+
+    #[test]
+    fn months_are_calendar_months() {
+        let now = Utc.with_ymd_and_hms(2024, 4, 30, 12, 0, 0).unwrap();
+
+        assert_eq!(
+            DateTimeParser::parse_at("2 months", now, Tz::UTC),
+            Ok(datetime("2024-02-29T12:00:00Z"))
+        );
+    }
+
+    #[test]
+    fn parses_calendar_words() {
+        assert_eq!(parse("today"), Ok(datetime("2024-03-04T00:00:00Z")));
+        assert_eq!(
+            parse("yesterday 09:00"),
+            Ok(datetime("2024-03-03T09:00:00Z"))
+        );
+        assert_eq!(parse("friday 17"), Ok(datetime("2024-03-01T17:00:00Z")));
+    }
+
+    #[test]
+    fn last_weekday_on_that_weekday_is_a_week_ago() {
+        assert_eq!(parse("last monday"), Ok(datetime("2024-02-26T00:00:00Z")));
+        assert_eq!(parse("monday"), Ok(datetime("2024-02-26T00:00:00Z")));
+    }
+
+    #[test]
+    fn uses_the_default_time_zone() {
+        let berlin = "Europe/Berlin".parse::<Tz>().unwrap();
+
+        assert_eq!(
+            DateTimeParser::parse_at("yesterday 09:00", now(), berlin),
+            Ok(datetime("2024-03-03T09:00:00+01:00"))
+        );
+    }
+
+    #[test]
+    fn parses_trailing_time_zones() {
+        assert_eq!(
+            parse("2024-06-01 12:00 Europe/Berlin"),
+            Ok(datetime("2024-06-01T12:00:00+02:00"))
+        );
+        assert_eq!(
+            parse("yesterday 09:00 Europe/Berlin"),
+            Ok(datetime("2024-03-03T09:00:00+01:00"))
+        );
+        assert_eq!(
+            parse("2024-03-01 10:00 +05:30"),
+            Ok(datetime("2024-03-01T10:00:00+05:30"))
+        );
+        assert_eq!(
+            parse("2024-03-01T10:00:00Z"),
+            Ok(datetime("2024-03-01T10:00:00Z"))
+        );
+    }
+
+    #[test]
+    fn rejects_nonexistent_local_times() {
+        assert_eq!(
+            parse("2024-03-31 02:30 Europe/Berlin"),
+            Err("Date time does not exist in the given time zone")
+        );
+    }
 
-        Err("Could not parse date time")
+    #[test]
+    fn rejects_invalid_inputs() {
+        assert!(parse("2024-13-01").is_err());
+        assert!(parse("tomorrow").is_err());
+        assert!(parse("2024-03-01 10:00 Mars/Olympus").is_err());
     }
 }
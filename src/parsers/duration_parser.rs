@@ -1,9 +1,12 @@
-use chrono::Duration;
+use chrono::{DateTime, Duration, Months, TimeZone};
+use log::trace;
 use regex::Regex;
 use std::ops::Deref;
 
 use super::Parser;
 
+const MONTH_DAYS: f64 = 30.0;
+
 #[derive(Debug, Clone)]
 pub struct DurationParser(Duration);
 
@@ -18,27 +21,165 @@ impl Deref for DurationParser {
 impl Parser for DurationParser {
     type Output = Duration;
 
+    /// See [`Span::parse`], a month counts as 30 days
     fn from_str(input: &str) -> Result<Duration, &'static str> {
-        println!("input: {}", input);
-        let re = Regex::new(r"(?i)^(\d+)\s*(day|week|hour|minute|second)s?\s*(ago)?$").unwrap();
+        trace!("Parsing duration '{}'", input);
+
+        Span::parse(input)
+            .and_then(|span| span.to_duration())
+            .ok_or("Could not parse duration")
+    }
+}
+
+/// Amount of time as written by the user. Whole months are kept apart from the
+/// rest because their length depends on the date they are applied to.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Span {
+    pub months: u32,
+    pub duration: Duration,
+}
+
+enum Unit {
+    Millis(f64),
+    Months,
+}
 
-        if let Some(caps) = re.captures(input) {
-            let amount: i64 = caps[1].parse().unwrap();
+impl Span {
+    /// One or more `<amount> <unit>` terms with an optional trailing "ago", e.g.
+    /// `30m`, `1h30m`, `1.5 hours`, `2 months, 3 days` or `90 seconds ago`.
+    /// Units: ms, s, m, h, d, w and mo in short, singular or plural form.
+    pub fn parse(input: &str) -> Option<Span> {
+        let input = input.trim().to_lowercase();
+        let input = input.strip_suffix("ago").unwrap_or(&input).trim_end();
 
-            let unit = &caps[2];
+        let term = r"\d+(?:\.\d+)?\s*[a-z]+";
+        let re = Regex::new(&format!(r"^{term}(?:\s*,?\s*{term})*$")).unwrap();
+
+        if !re.is_match(input) {
+            return None;
+        }
 
-            let duration = match unit {
-                "day" => Duration::days(amount),
-                "week" => Duration::weeks(amount),
-                "hour" => Duration::hours(amount),
-                "minute" => Duration::minutes(amount),
-                "second" => Duration::seconds(amount),
-                _ => unreachable!(),
+        let re = Regex::new(r"(\d+(?:\.\d+)?)\s*([a-z]+)").unwrap();
+
+        let mut span = Span::default();
+
+        for caps in re.captures_iter(input) {
+            let amount: f64 = caps[1].parse().ok()?;
+
+            let millis = match Self::unit(&caps[2])? {
+                Unit::Months if amount.fract() == 0.0 => {
+                    span.months = span.months.checked_add(amount as u32)?;
+                    continue;
+                }
+                Unit::Months => amount * MONTH_DAYS * 86_400_000.0,
+                Unit::Millis(unit) => amount * unit,
             };
 
-            return Ok(duration);
+            span.duration = span
+                .duration
+                .checked_add(&Duration::try_milliseconds(millis.round() as i64)?)?;
         }
 
-        Err("Could not parse duration")
+        Some(span)
+    }
+
+    fn unit(unit: &str) -> Option<Unit> {
+        let millis = match unit {
+            "ms" | "msec" | "msecs" | "millisecond" | "milliseconds" => 1.0,
+            "s" | "sec" | "secs" | "second" | "seconds" => 1_000.0,
+            "m" | "min" | "mins" | "minute" | "minutes" => 60_000.0,
+            "h" | "hr" | "hrs" | "hour" | "hours" => 3_600_000.0,
+            "d" | "day" | "days" => 86_400_000.0,
+            "w" | "wk" | "wks" | "week" | "weeks" => 604_800_000.0,
+            "mo" | "mon" | "month" | "months" => return Some(Unit::Months),
+            _ => return None,
+        };
+
+        Some(Unit::Millis(millis))
+    }
+
+    /// Fixed length, counting a month as 30 days
+    pub fn to_duration(self) -> Option<Duration> {
+        Duration::try_days(self.months as i64 * MONTH_DAYS as i64)?.checked_add(&self.duration)
+    }
+
+    /// `datetime` moved back by this span, using calendar months
+    pub fn before<T: TimeZone>(&self, datetime: DateTime<T>) -> Option<DateTime<T>> {
+        datetime
+            .checked_sub_months(Months::new(self.months))?
+            .checked_sub_signed(self.duration)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+
+    use super::*;
+
+    #[test]
+    fn parses_single_terms() {
+        assert_eq!(DurationParser::from_str("30m"), Ok(Duration::minutes(30)));
+        assert_eq!(DurationParser::from_str("2 days"), Ok(Duration::days(2)));
+        assert_eq!(
+            DurationParser::from_str("500ms"),
+            Ok(Duration::milliseconds(500))
+        );
+    }
+
+    #[test]
+    fn parses_fractions_and_ago() {
+        assert_eq!(
+            DurationParser::from_str("1.5 hours"),
+            Ok(Duration::minutes(90))
+        );
+        assert_eq!(
+            DurationParser::from_str("90 seconds ago"),
+            Ok(Duration::seconds(90))
+        );
+    }
+
+    #[test]
+    fn parses_several_terms() {
+        assert_eq!(DurationParser::from_str("1h30m"), Ok(Duration::minutes(90)));
+        assert_eq!(
+            DurationParser::from_str("1 week, 2 days"),
+            Ok(Duration::days(9))
+        );
+    }
+
+    #[test]
+    fn months_count_as_30_days() {
+        assert_eq!(DurationParser::from_str("2 months"), Ok(Duration::days(60)));
+        assert_eq!(DurationParser::from_str("1.5mo"), Ok(Duration::days(45)));
+    }
+
+    #[test]
+    fn spans_keep_whole_months_apart() {
+        assert_eq!(
+            Span::parse("2 months 3 days"),
+            Some(Span {
+                months: 2,
+                duration: Duration::days(3),
+            })
+        );
+    }
+
+    #[test]
+    fn spans_move_back_by_calendar_months() {
+        let datetime = Utc.with_ymd_and_hms(2024, 3, 31, 12, 0, 0).unwrap();
+
+        assert_eq!(
+            Span::parse("1 month").unwrap().before(datetime),
+            Some(Utc.with_ymd_and_hms(2024, 2, 29, 12, 0, 0).unwrap())
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_inputs() {
+        assert!(DurationParser::from_str("").is_err());
+        assert!(DurationParser::from_str("5 parsecs").is_err());
+        assert!(DurationParser::from_str("m30").is_err());
+        assert!(DurationParser::from_str("ago").is_err());
     }
 }
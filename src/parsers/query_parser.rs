@@ -1,31 +1,71 @@
-use std::collections::HashMap;
+use chrono::{DateTime, FixedOffset, TimeDelta};
 
-use chrono::{NaiveDateTime, TimeDelta};
+use crate::config::{DataSource, CONFIG};
 
-use crate::config::DataSource;
+use super::{BucketParser, DateTimeParser, DurationParser, Parser};
 
-use super::{DatasetParser, DateTimeParser, DurationParser, Parser};
+use lexer::{Lexer, Token, TokenKind};
 
-use std::fmt;
+pub use lexer::Location;
+
+use std::{fmt, str::FromStr};
+
+mod lexer;
 
 #[derive(Debug)]
 pub enum QueryParserError {
-    InvalidSelect(String),
-    InvalidFrom(String),
-    InvalidWhere(String),
-    InvalidTime(String),
-    InvalidCorrelate(String),
+    InvalidSelect(String, Location),
+    InvalidFrom(String, Location),
+    InvalidTime(String, Location),
+    InvalidHaving(String, Location),
+    InvalidCorrelate(String, Location),
+    UnknownDataSource(String, Location),
+    UnexpectedToken {
+        expected: String,
+        found: String,
+        location: Location,
+    },
+    UnexpectedCharacter(char, Location),
+    UnterminatedString(Location),
+}
+
+impl QueryParserError {
+    pub fn location(&self) -> &Location {
+        match self {
+            QueryParserError::InvalidSelect(_, location)
+            | QueryParserError::InvalidFrom(_, location)
+            | QueryParserError::InvalidTime(_, location)
+            | QueryParserError::InvalidHaving(_, location)
+            | QueryParserError::InvalidCorrelate(_, location)
+            | QueryParserError::UnknownDataSource(_, location)
+            | QueryParserError::UnexpectedToken { location, .. }
+            | QueryParserError::UnexpectedCharacter(_, location)
+            | QueryParserError::UnterminatedString(location) => location,
+        }
+    }
 }
 
 impl fmt::Display for QueryParserError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            QueryParserError::InvalidSelect(msg) => write!(f, "Invalid SELECT: {}", msg),
-            QueryParserError::InvalidFrom(msg) => write!(f, "Invalid FROM: {}", msg),
-            QueryParserError::InvalidWhere(msg) => write!(f, "Invalid WHERE: {}", msg),
-            QueryParserError::InvalidTime(msg) => write!(f, "Invalid time: {}", msg),
-            QueryParserError::InvalidCorrelate(msg) => write!(f, "Invalid correlate: {}", msg),
-        }
+            QueryParserError::InvalidSelect(msg, _) => write!(f, "Invalid SELECT: {}", msg),
+            QueryParserError::InvalidFrom(msg, _) => write!(f, "Invalid FROM: {}", msg),
+            QueryParserError::InvalidTime(msg, _) => write!(f, "Invalid time: {}", msg),
+            QueryParserError::InvalidHaving(msg, _) => write!(f, "Invalid HAVING: {}", msg),
+            QueryParserError::InvalidCorrelate(msg, _) => write!(f, "Invalid correlate: {}", msg),
+            QueryParserError::UnknownDataSource(id, _) => {
+                write!(f, "Unknown data source '{}'", id)
+            }
+            QueryParserError::UnexpectedToken {
+                expected, found, ..
+            } => write!(f, "Expected {}, found {}", expected, found),
+            QueryParserError::UnexpectedCharacter(c, _) => {
+                write!(f, "Unexpected character '{}'", c)
+            }
+            QueryParserError::UnterminatedString(_) => write!(f, "Unterminated string literal"),
+        }?;
+
+        write!(f, " at {}", self.location())
     }
 }
 
@@ -39,6 +79,48 @@ pub enum Select {
     Column(Column),
     Count(Option<Column>),
     Average(Column),
+    Min(Column),
+    Max(Column),
+    Sum(Column),
+    /// Percentile between 0 and 100
+    Percentile(Column, f64),
+    UniqueCount(Column),
+    /// Aggregate per duration of the queried time window
+    Rate(Box<Select>, TimeDelta),
+    As(Box<Select>, String),
+}
+
+impl Select {
+    pub fn is_aggregate(&self) -> bool {
+        match self {
+            Select::All | Select::Column(_) => false,
+            Select::As(select, _) => select.is_aggregate(),
+            _ => true,
+        }
+    }
+
+    /// Name of the result column of an aggregate. Without `AS` it is derived from
+    /// the function and column, e.g. `count`, `avg_<column>` or `p95_<column>`, so
+    /// several aggregates don't collide.
+    pub fn alias(&self) -> Option<String> {
+        let alias = match self {
+            Select::All | Select::Column(_) => return None,
+            Select::Count(None) => "count".to_string(),
+            Select::Count(Some(column)) => format!("count_{}", column),
+            Select::Average(column) => format!("avg_{}", column),
+            Select::Min(column) => format!("min_{}", column),
+            Select::Max(column) => format!("max_{}", column),
+            Select::Sum(column) => format!("sum_{}", column),
+            Select::Percentile(column, percentile) => {
+                format!("p{}_{}", percentile.to_string().replace('.', "_"), column)
+            }
+            Select::UniqueCount(column) => format!("unique_count_{}", column),
+            Select::Rate(select, _) => format!("rate_{}", select.alias()?),
+            Select::As(_, alias) => alias.clone(),
+        };
+
+        Some(alias)
+    }
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -53,32 +135,174 @@ pub enum Where {
     Like(Column, String),
 }
 
+impl Where {
+    pub fn column(&self) -> &str {
+        match self {
+            Where::Equals(column, _)
+            | Where::NotEquals(column, _)
+            | Where::In(column, _)
+            | Where::GreaterThan(column, _)
+            | Where::LessThan(column, _)
+            | Where::GreaterThanOrEqual(column, _)
+            | Where::LessThanOrEqual(column, _)
+            | Where::Like(column, _) => column,
+        }
+    }
+
+    pub fn values(&self) -> Vec<&str> {
+        match self {
+            Where::In(_, values) => values.iter().map(String::as_str).collect(),
+            Where::Equals(_, value)
+            | Where::NotEquals(_, value)
+            | Where::GreaterThan(_, value)
+            | Where::LessThan(_, value)
+            | Where::GreaterThanOrEqual(_, value)
+            | Where::LessThanOrEqual(_, value)
+            | Where::Like(_, value) => vec![value],
+        }
+    }
+}
+
+/// Boolean expression over `Where` leaves. `Group` records explicit parentheses.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Condition {
+    Where(Where),
+    And(Box<Condition>, Box<Condition>),
+    Or(Box<Condition>, Box<Condition>),
+    Not(Box<Condition>),
+    Group(Box<Condition>),
+}
+
+impl From<Where> for Condition {
+    fn from(condition: Where) -> Self {
+        Condition::Where(condition)
+    }
+}
+
+impl Condition {
+    /// Renders the expression with `AND`, `OR`, `NOT` and parentheses, which SQL and
+    /// NRQL share, using `leaf` for the individual comparisons. The output can be
+    /// joined with other conditions using `AND` without changing its meaning.
+    pub fn render<E>(&self, leaf: &impl Fn(&Where) -> Result<String, E>) -> Result<String, E> {
+        match self {
+            Condition::Or(..) => Ok(format!("({})", self.render_inner(leaf)?)),
+            _ => self.render_inner(leaf),
+        }
+    }
+
+    fn render_inner<E>(&self, leaf: &impl Fn(&Where) -> Result<String, E>) -> Result<String, E> {
+        match self {
+            Condition::Where(condition) => leaf(condition),
+            Condition::And(left, right) => Ok(format!(
+                "{} AND {}",
+                left.render(leaf)?,
+                right.render(leaf)?
+            )),
+            Condition::Or(left, right) => Ok(format!(
+                "{} OR {}",
+                left.render_inner(leaf)?,
+                right.render_inner(leaf)?
+            )),
+            Condition::Not(condition) => match condition.as_ref() {
+                Condition::And(..) | Condition::Or(..) => {
+                    Ok(format!("NOT ({})", condition.render_inner(leaf)?))
+                }
+                _ => Ok(format!("NOT {}", condition.render_inner(leaf)?)),
+            },
+            Condition::Group(condition) => Ok(format!("({})", condition.render_inner(leaf)?)),
+        }
+    }
+
+    /// Evaluates the expression, using `leaf` for the individual comparisons
+    pub fn evaluate(&self, leaf: &impl Fn(&Where) -> bool) -> bool {
+        match self {
+            Condition::Where(condition) => leaf(condition),
+            Condition::And(left, right) => left.evaluate(leaf) && right.evaluate(leaf),
+            Condition::Or(left, right) => left.evaluate(leaf) || right.evaluate(leaf),
+            Condition::Not(condition) => !condition.evaluate(leaf),
+            Condition::Group(condition) => condition.evaluate(leaf),
+        }
+    }
+
+    pub fn leaves(&self) -> Vec<&Where> {
+        match self {
+            Condition::Where(condition) => vec![condition],
+            Condition::And(left, right) | Condition::Or(left, right) => {
+                let mut leaves = left.leaves();
+                leaves.extend(right.leaves());
+                leaves
+            }
+            Condition::Not(condition) | Condition::Group(condition) => condition.leaves(),
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct Facet(pub String);
 
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum Order {
+    #[default]
+    Asc,
+    Desc,
+}
+
+impl fmt::Display for Order {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Order::Asc => write!(f, "ASC"),
+            Order::Desc => write!(f, "DESC"),
+        }
+    }
+}
+
+/// Sort key, either a column or the alias of an aggregate
 #[derive(Debug, PartialEq, Clone)]
-pub struct QueryInput {
-    pub select: Vec<Select>,
-    pub conditions: Vec<Where>,
-    pub facet: Vec<Facet>,
-    pub since: Option<NaiveDateTime>,
-    pub until: Option<NaiveDateTime>,
-    pub correlate: Option<Correlate>,
+pub struct OrderBy {
+    pub column: Column,
+    pub order: Order,
 }
 
-impl Default for QueryInput {
-    fn default() -> Self {
-        Self {
-            select: Vec::new(),
-            conditions: Vec::new(),
-            facet: Vec::new(),
-            since: None,
-            until: None,
-            correlate: None,
+impl FromStr for OrderBy {
+    type Err = String;
+
+    /// `<column>[:asc|:desc]`, e.g. `count:desc`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (column, order) = match s.rsplit_once(':') {
+            Some((column, order)) if order.eq_ignore_ascii_case("asc") => (column, Order::Asc),
+            Some((column, order)) if order.eq_ignore_ascii_case("desc") => (column, Order::Desc),
+            Some((_, order)) => return Err(format!("Unknown sort order '{}'", order)),
+            None => (s, Order::Asc),
+        };
+
+        if column.is_empty() {
+            return Err("Sort column missing".to_string());
         }
+
+        Ok(OrderBy {
+            column: column.to_string(),
+            order,
+        })
     }
 }
 
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct QueryInput {
+    pub select: Vec<Select>,
+    /// Combined with AND
+    pub conditions: Vec<Condition>,
+    pub facet: Vec<Facet>,
+    /// Conditions on aggregate aliases, combined with AND
+    pub having: Vec<Condition>,
+    pub since: Option<DateTime<FixedOffset>>,
+    pub until: Option<DateTime<FixedOffset>>,
+    /// Width of the time buckets rows are counted in
+    pub timeseries: Option<TimeDelta>,
+    pub order_by: Vec<OrderBy>,
+    pub limit: Option<usize>,
+    pub correlate: Option<Correlate>,
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct Correlate {
     pub data_source: DataSource,
@@ -99,329 +323,974 @@ pub enum CorrelateCondition {
     },
 }
 
-pub struct QueryParser;
+const CLAUSE_KEYWORDS: [&str; 12] = [
+    "SELECT",
+    "FROM",
+    "WHERE",
+    "FACET",
+    "GROUP",
+    "HAVING",
+    "SINCE",
+    "UNTIL",
+    "TIMESERIES",
+    "ORDER",
+    "LIMIT",
+    "CORRELATE",
+];
+
+pub struct QueryParser<'a> {
+    source: &'a str,
+    /// Data sources the ids in FROM and CORRELATE are looked up in
+    known_data_sources: &'a [DataSource],
+    tokens: Vec<Token>,
+    position: usize,
+}
 
 type QueryParserOutput = (QueryInput, Vec<DataSource>);
 
-impl QueryParser {
-    pub fn parse(query: &str) -> Result<QueryParserOutput, QueryParserError> {
-        let keywords = [
-            "SELECT",
-            "FROM",
-            "WHERE",
-            "FACET",
-            "SINCE",
-            "UNTIL",
-            "CORRELATE",
-        ];
-        let mut query_by_keyword: HashMap<&str, String> = HashMap::new();
-        let mut current_key = "";
-
-        for token in query.split_whitespace() {
-            if keywords.contains(&token.to_uppercase().as_str()) {
-                current_key = token;
-            } else {
-                query_by_keyword
-                    .entry(current_key)
-                    .or_default()
-                    .push_str(&format!(" {}", token));
-            }
-        }
+impl<'a> QueryParser<'a> {
+    /// Parses a raw query of the form
+    ///
+    /// `SELECT <select> FROM <ids> [WHERE <conditions>] [FACET|GROUP BY <columns>]
+    /// [HAVING <conditions>] [SINCE <time>] [UNTIL <time>] [TIMESERIES <bucket>] [ORDER BY <column> [ASC|DESC], ...]
+    /// [LIMIT <n>] [CORRELATE [WITH] <id> ON <conditions>]`
+    ///
+    /// Clauses may appear in any order. Keywords are case-insensitive and values
+    /// containing whitespace or reserved characters can be quoted with `'` or `"`.
+    pub fn parse(query: &'a str) -> Result<QueryParserOutput, QueryParserError> {
+        Self::parse_with(query, CONFIG.data_sources())
+    }
+
+    /// Like [`QueryParser::parse`] with the given data sources instead of the configured ones
+    pub fn parse_with(
+        query: &'a str,
+        known_data_sources: &'a [DataSource],
+    ) -> Result<QueryParserOutput, QueryParserError> {
+        let mut parser = QueryParser {
+            source: query,
+            known_data_sources,
+            tokens: Lexer::new(query).tokenize()?,
+            position: 0,
+        };
 
         let mut input = QueryInput::default();
         let mut data_sources = Vec::new();
-
-        let handlers: Vec<(
-            &str,
-            fn(&str, &mut QueryInput) -> Result<(), QueryParserError>,
-        )> = vec![
-            ("SELECT", Self::handle_select),
-            ("WHERE", Self::handle_conditions),
-            ("FACET", Self::handle_facet),
-            ("SINCE", Self::handle_time),
-            ("UNTIL", Self::handle_time),
-            ("CORRELATE", Self::handle_correlate),
-        ];
-
-        for (key, handler) in handlers {
-            if let Some(clause) = query_by_keyword.get(key) {
-                handler(clause.trim(), &mut input)?;
+        let mut having_location = None;
+
+        while parser.peek().kind != TokenKind::Eof {
+            let token = parser.advance();
+
+            if token.is_keyword("SELECT") {
+                input.select.extend(parser.select_list()?);
+            } else if token.is_keyword("FROM") {
+                data_sources.extend(parser.data_sources()?);
+            } else if token.is_keyword("WHERE") {
+                input.conditions.push(parser.or_condition()?);
+            } else if token.is_keyword("FACET") {
+                input.facet.extend(parser.facet_list()?);
+            } else if token.is_keyword("GROUP") {
+                parser.expect_keyword("BY")?;
+                input.facet.extend(parser.facet_list()?);
+            } else if token.is_keyword("HAVING") {
+                having_location.get_or_insert(token.location);
+                input.having.push(parser.or_condition()?);
+            } else if token.is_keyword("SINCE") {
+                input.since = Some(parser.time()?);
+            } else if token.is_keyword("UNTIL") {
+                input.until = Some(parser.time()?);
+            } else if token.is_keyword("TIMESERIES") {
+                input.timeseries = Some(parser.bucket()?);
+            } else if token.is_keyword("ORDER") {
+                parser.expect_keyword("BY")?;
+                input.order_by.extend(parser.order_by_list()?);
+            } else if token.is_keyword("LIMIT") {
+                input.limit = Some(parser.limit()?);
+            } else if token.is_keyword("CORRELATE") {
+                input.correlate = Some(parser.correlate()?);
+            } else {
+                return Err(unexpected(
+                    "SELECT, FROM, WHERE, FACET, HAVING, SINCE, UNTIL, TIMESERIES, ORDER BY, LIMIT or CORRELATE",
+                    &token,
+                ));
             }
         }
 
-        Self::handle_from(
-            query_by_keyword
-                .get("FROM")
-                .ok_or(QueryParserError::InvalidFrom(
-                    "FROM clause missing".to_string(),
-                ))?,
-            &mut data_sources,
-        )?;
+        if data_sources.is_empty() {
+            return Err(QueryParserError::InvalidFrom(
+                "FROM clause missing".to_string(),
+                parser.peek().location,
+            ));
+        }
+
+        if let Some(location) = having_location {
+            Self::validate_having(&input, location)?;
+        }
 
         Ok((input, data_sources))
     }
 
-    fn handle_select(select_clause: &str, input: &mut QueryInput) -> Result<(), QueryParserError> {
-        let select = select_clause
-            .split(',')
-            .map(|item| {
-                let item = item.trim();
-                if item == "*" {
-                    Select::All
-                } else if item.to_uppercase().starts_with("COUNT(") {
-                    let inner = item[6..item.len() - 1].trim();
-                    if inner == "*" || inner.is_empty() {
-                        Select::Count(None)
-                    } else {
-                        Select::Count(Some(inner.to_string()))
-                    }
-                } else if item.to_uppercase().starts_with("AVG(") {
-                    let inner = item[4..item.len() - 1].trim();
-                    Select::Average(inner.to_string())
-                } else {
-                    Select::Column(item.to_string())
-                }
-            })
-            .collect::<Vec<Select>>();
-
-        if select.is_empty() {
-            return Err(QueryParserError::InvalidSelect(
-                "Invalid select".to_string(),
+    /// HAVING compares aggregate aliases with numbers, so it means the same whether
+    /// a data source filters natively or the rows are filtered after the query
+    fn validate_having(input: &QueryInput, location: Location) -> Result<(), QueryParserError> {
+        let aliases = input
+            .select
+            .iter()
+            .filter(|select| select.is_aggregate())
+            .filter_map(Select::alias)
+            .collect::<Vec<String>>();
+
+        if aliases.is_empty() {
+            return Err(QueryParserError::InvalidHaving(
+                "HAVING requires an aggregate in SELECT".to_string(),
+                location,
             ));
         }
 
-        input.select.extend(select);
+        for condition in input.having.iter().flat_map(Condition::leaves) {
+            if !aliases.iter().any(|alias| alias == condition.column()) {
+                return Err(QueryParserError::InvalidHaving(
+                    format!(
+                        "'{}' is no aggregate, expected one of: {}",
+                        condition.column(),
+                        aliases.join(", ")
+                    ),
+                    location,
+                ));
+            }
+
+            if let Where::Like(..) = condition {
+                return Err(QueryParserError::InvalidHaving(
+                    "LIKE cannot be used on aggregates".to_string(),
+                    location,
+                ));
+            }
+
+            for value in condition.values() {
+                if !value.parse::<f64>().is_ok_and(f64::is_finite) {
+                    return Err(QueryParserError::InvalidHaving(
+                        format!("Expected a number, found '{}'", value),
+                        location,
+                    ));
+                }
+            }
+        }
 
         Ok(())
     }
 
-    fn handle_from(
-        from_clause: &str,
-        data_sources: &mut Vec<DataSource>,
-    ) -> Result<(), QueryParserError> {
-        let parsed = DatasetParser::from_str(from_clause)
-            .map_err(|err| QueryParserError::InvalidFrom(err.to_string()))?;
+    fn select_list(&mut self) -> Result<Vec<Select>, QueryParserError> {
+        let mut select = vec![self.select_item()?];
 
-        if parsed.is_empty() {
-            return Err(QueryParserError::InvalidFrom(
-                "No valid data source ids".to_string(),
-            ));
+        while self.eat(&TokenKind::Comma) {
+            select.push(self.select_item()?);
         }
 
-        data_sources.extend(parsed);
+        Ok(select)
+    }
+
+    fn select_item(&mut self) -> Result<Select, QueryParserError> {
+        let location = self.peek().location;
+        let select = self.select_expression()?;
 
-        Ok(())
+        if !self.eat_keyword("AS") {
+            return Ok(select);
+        }
+
+        if select == Select::All {
+            return Err(QueryParserError::InvalidSelect(
+                "* cannot be aliased".to_string(),
+                location,
+            ));
+        }
+
+        Ok(Select::As(Box::new(select), self.value()?))
     }
 
-    fn handle_conditions(
-        conditions_str: &str,
-        input: &mut QueryInput,
-    ) -> Result<(), QueryParserError> {
-        let mut conditions: Vec<Where> = Vec::new();
+    fn select_expression(&mut self) -> Result<Select, QueryParserError> {
+        let (name, location) = self.word("column, * or aggregate function")?;
 
-        for condition_str in conditions_str.split("AND").map(str::trim) {
-            let parts: Vec<&str> = condition_str
-                .splitn(3, |c: char| c.is_whitespace())
-                .collect();
-            if parts.len() != 3 {
-                return Err(QueryParserError::InvalidWhere(condition_str.to_string()));
-            }
+        if name == "*" {
+            return Ok(Select::All);
+        }
 
-            let field: String = parts[0].into();
-            let operator = parts[1];
-            let value_str: String = parts[2].into();
-
-            match operator {
-                "IN" => {
-                    let values = value_str
-                        .trim_start_matches('(')
-                        .trim_end_matches(')')
-                        .split(',')
-                        .map(|s| s.trim().to_string())
-                        .collect();
-                    conditions.push(Where::In(field, values));
-                }
-                "=" => {
-                    conditions.push(Where::Equals(field, value_str));
-                }
-                "!=" => {
-                    conditions.push(Where::NotEquals(field, value_str));
-                }
-                ">" => {
-                    conditions.push(Where::GreaterThan(field, value_str));
-                }
-                "<" => {
-                    conditions.push(Where::LessThan(field, value_str));
-                }
-                ">=" => {
-                    conditions.push(Where::GreaterThanOrEqual(field, value_str));
-                }
-                "<=" => {
-                    conditions.push(Where::LessThanOrEqual(field, value_str));
+        if !self.eat(&TokenKind::LeftParen) {
+            return Ok(Select::Column(name));
+        }
+
+        let select = match name.to_uppercase().as_str() {
+            "COUNT" => match &self.peek().kind {
+                TokenKind::RightParen => Select::Count(None),
+                TokenKind::Word(word) if word == "*" => {
+                    self.advance();
+                    Select::Count(None)
                 }
-                "LIKE" => {
-                    conditions.push(Where::Like(field, value_str));
+                _ => Select::Count(Some(self.word("column or *")?.0)),
+            },
+            "AVG" | "AVERAGE" => Select::Average(self.word("column")?.0),
+            "MIN" => Select::Min(self.word("column")?.0),
+            "MAX" => Select::Max(self.word("column")?.0),
+            "SUM" => Select::Sum(self.word("column")?.0),
+            "UNIQUECOUNT" | "UNIQUE_COUNT" => Select::UniqueCount(self.word("column")?.0),
+            "PERCENTILE" => {
+                let column = self.word("column")?.0;
+
+                self.expect(&TokenKind::Comma)?;
+
+                let (percentile, location) = self.word("percentile")?;
+
+                match percentile.parse::<f64>() {
+                    Ok(percentile) if percentile > 0.0 && percentile <= 100.0 => {
+                        Select::Percentile(column, percentile)
+                    }
+                    _ => {
+                        return Err(QueryParserError::InvalidSelect(
+                            format!(
+                                "Percentile has to be between 0 and 100, found '{}'",
+                                percentile
+                            ),
+                            location,
+                        ))
+                    }
                 }
-                _ => {
-                    return Err(QueryParserError::InvalidWhere(format!(
-                        "Unsupported operator in condition: {}",
-                        condition_str
-                    )));
+            }
+            "RATE" => {
+                let select = self.select_expression()?;
+
+                if !select.is_aggregate() || matches!(select, Select::Rate(..)) {
+                    return Err(QueryParserError::InvalidSelect(
+                        "RATE requires an aggregate function, e.g. RATE(COUNT(*), 1 minute)"
+                            .to_string(),
+                        location,
+                    ));
                 }
+
+                self.expect(&TokenKind::Comma)?;
+
+                let (input, location) =
+                    self.raw_text("duration", |token| token.kind == TokenKind::RightParen)?;
+
+                let per = BucketParser::from_str(&input).map_err(|e| {
+                    QueryParserError::InvalidSelect(format!("{} '{}'", e, input), location)
+                })?;
+
+                Select::Rate(Box::new(select), per)
             }
+            _ => {
+                return Err(QueryParserError::InvalidSelect(
+                    format!("Unknown function '{}'", name),
+                    location,
+                ))
+            }
+        };
+
+        self.expect(&TokenKind::RightParen)?;
+
+        Ok(select)
+    }
+
+    fn data_sources(&mut self) -> Result<Vec<DataSource>, QueryParserError> {
+        let mut data_sources = vec![self.data_source()?];
+
+        while self.eat(&TokenKind::Comma) {
+            data_sources.push(self.data_source()?);
         }
 
-        input.conditions.extend(conditions);
+        Ok(data_sources)
+    }
 
-        Ok(())
+    fn data_source(&mut self) -> Result<DataSource, QueryParserError> {
+        let (id, location) = self.word("data source id")?;
+
+        self.find_data_source(&id)
+            .ok_or(QueryParserError::UnknownDataSource(id, location))
     }
 
-    fn handle_facet(facet_str: &str, input: &mut QueryInput) -> Result<(), QueryParserError> {
-        let facet_str = facet_str.trim();
+    fn find_data_source(&self, id: &str) -> Option<DataSource> {
+        self.known_data_sources
+            .iter()
+            .find(|data_source| data_source.id == id)
+            .cloned()
+    }
 
-        let facet = facet_str
-            .split(',')
-            .filter(|s| !s.is_empty())
-            .map(|s| Facet(s.trim().to_string()))
-            .collect::<Vec<Facet>>();
+    fn facet_list(&mut self) -> Result<Vec<Facet>, QueryParserError> {
+        let mut facet = vec![Facet(self.word("column")?.0)];
 
-        input.facet.extend(facet);
+        while self.eat(&TokenKind::Comma) {
+            facet.push(Facet(self.word("column")?.0));
+        }
 
-        Ok(())
+        Ok(facet)
     }
 
-    fn handle_time(since_str: &str, input: &mut QueryInput) -> Result<(), QueryParserError> {
-        match DateTimeParser::from_str(since_str.trim()) {
-            Ok(dt) => {
-                input.since = Some(dt);
-                Ok(())
-            }
-            Err(e) => Err(QueryParserError::InvalidTime(e.to_string())),
+    /// `or := and (OR and)*`
+    fn or_condition(&mut self) -> Result<Condition, QueryParserError> {
+        let mut condition = self.and_condition()?;
+
+        while self.eat_keyword("OR") {
+            condition = Condition::Or(Box::new(condition), Box::new(self.and_condition()?));
         }
+
+        Ok(condition)
     }
 
-    fn handle_correlate(
-        correlate_str: &str,
-        input: &mut QueryInput,
-    ) -> Result<(), QueryParserError> {
-        let parts: Vec<&str> = correlate_str.splitn(2, "ON").collect();
-        if parts.len() != 2 {
-            return Err(QueryParserError::InvalidCorrelate(
-                "Missing ON clause".to_string(),
-            ));
+    /// `and := not (AND not)*`
+    fn and_condition(&mut self) -> Result<Condition, QueryParserError> {
+        let mut condition = self.not_condition()?;
+
+        while self.eat_keyword("AND") {
+            condition = Condition::And(Box::new(condition), Box::new(self.not_condition()?));
         }
 
-        let data_source_id = parts[0].trim().trim_start_matches("WITH ").trim();
-        let conditions_str = parts[1].trim();
+        Ok(condition)
+    }
 
-        let data_source = DatasetParser::from_id(data_source_id)
-            .ok_or(QueryParserError::InvalidCorrelate(
-                "Invalid data source id".to_string(),
-            ))
-            .unwrap();
+    /// `not := NOT not | '(' or ')' | <column> <comparison>`
+    fn not_condition(&mut self) -> Result<Condition, QueryParserError> {
+        if self.eat_keyword("NOT") {
+            return Ok(Condition::Not(Box::new(self.not_condition()?)));
+        }
 
-        let mut query_input = QueryInput::default();
-        let mut dependent_conditions: Vec<CorrelateCondition> = Vec::new();
+        if self.eat(&TokenKind::LeftParen) {
+            let condition = self.or_condition()?;
+            self.expect(&TokenKind::RightParen)?;
 
-        for condition in conditions_str.split("AND").map(str::trim) {
-            if condition.contains("WITHIN") {
-                let parts: Vec<&str> = condition.split("WITHIN").collect();
-                if parts.len() != 2 {
-                    return Err(QueryParserError::InvalidCorrelate(
-                        "Missing WITHIN clause".to_string(),
-                    ));
-                }
+            return Ok(Condition::Group(Box::new(condition)));
+        }
 
-                let field0_str = parts[0].trim();
-                let within_str = parts[1].trim();
-                let within_parts: Vec<&str> = within_str.splitn(2, "OF").collect();
-                if within_parts.len() != 2 {
-                    return Err(QueryParserError::InvalidCorrelate(
-                        "Missing OF clause".to_string(),
-                    ));
-                }
+        let (column, _) = self.word("column, NOT or '('")?;
 
-                let time_str = within_parts[0].trim();
-                let field1_str = within_parts[1].trim();
+        self.comparison(column)
+    }
 
-                let duration = DurationParser::from_str(time_str).map_err(|e| {
-                    QueryParserError::InvalidCorrelate(format!("Invalid duration: {}", e))
-                })?;
+    /// Parses the operator and value(s) of a comparison whose column was already consumed.
+    fn comparison(&mut self, column: Column) -> Result<Condition, QueryParserError> {
+        if self.eat_keyword("NOT") {
+            let token = self.peek();
 
-                let [f0_id, f0_col] = Self::handle_correlate_field(field0_str)?;
-                let [_f1_id, f1_col] = Self::handle_correlate_field(field1_str)?;
+            if !token.is_keyword("LIKE") && !token.is_keyword("IN") {
+                return Err(unexpected("LIKE or IN", token));
+            }
 
-                dependent_conditions.push(if f0_id == data_source.id {
-                    CorrelateCondition::Within {
-                        parent: f1_col.into(),
-                        child: f0_col.into(),
-                        delta: duration,
-                    }
-                } else {
-                    CorrelateCondition::Within {
-                        parent: f0_col.into(),
-                        child: f1_col.into(),
-                        delta: duration,
-                    }
-                });
-            } else if condition.contains("IS") {
-                let parts = condition.split("IS").map(str::trim).collect::<Vec<&str>>();
+            return Ok(Condition::Not(Box::new(self.comparison(column)?)));
+        }
 
-                if parts.len() != 2 {
-                    return Err(QueryParserError::InvalidCorrelate(
-                        "Missing IS clause".to_string(),
-                    ));
+        let token = self.advance();
+
+        let condition = match token.kind {
+            TokenKind::Equals => Where::Equals(column, self.value()?),
+            TokenKind::NotEquals => Where::NotEquals(column, self.value()?),
+            TokenKind::GreaterThan => Where::GreaterThan(column, self.value()?),
+            TokenKind::LessThan => Where::LessThan(column, self.value()?),
+            TokenKind::GreaterThanOrEqual => Where::GreaterThanOrEqual(column, self.value()?),
+            TokenKind::LessThanOrEqual => Where::LessThanOrEqual(column, self.value()?),
+            _ if token.is_keyword("LIKE") => Where::Like(column, self.value()?),
+            _ if token.is_keyword("IN") => {
+                self.expect(&TokenKind::LeftParen)?;
+
+                let mut values = vec![self.value()?];
+
+                while self.eat(&TokenKind::Comma) {
+                    values.push(self.value()?);
                 }
 
-                let [f0_id, f0_col] = Self::handle_correlate_field(parts[0])?;
-                let [_f1_id, f1_col] = Self::handle_correlate_field(parts[1])?;
+                self.expect(&TokenKind::RightParen)?;
 
-                dependent_conditions.push(if f0_id == data_source.id {
-                    CorrelateCondition::Is {
-                        parent: f1_col.into(),
-                        child: f0_col.into(),
-                    }
-                } else {
-                    CorrelateCondition::Is {
-                        parent: f0_col.into(),
-                        child: f1_col.into(),
-                    }
-                });
+                Where::In(column, values)
+            }
+            _ => return Err(unexpected("=, !=, <, <=, >, >=, IN, LIKE or NOT", &token)),
+        };
+
+        Ok(Condition::Where(condition))
+    }
+
+    fn value(&mut self) -> Result<String, QueryParserError> {
+        let token = self.advance();
+
+        match token.kind {
+            TokenKind::Word(value) | TokenKind::Str(value) => Ok(value),
+            _ => Err(unexpected("value", &token)),
+        }
+    }
+
+    fn time(&mut self) -> Result<DateTime<FixedOffset>, QueryParserError> {
+        let (input, location) = self.raw_text("date, datetime or duration", |token| {
+            CLAUSE_KEYWORDS
+                .iter()
+                .any(|keyword| token.is_keyword(keyword))
+        })?;
+
+        DateTimeParser::from_str(&input)
+            .map_err(|e| QueryParserError::InvalidTime(format!("{} '{}'", e, input), location))
+    }
+
+    fn order_by_list(&mut self) -> Result<Vec<OrderBy>, QueryParserError> {
+        let mut order_by = vec![self.order_by_item()?];
+
+        while self.eat(&TokenKind::Comma) {
+            order_by.push(self.order_by_item()?);
+        }
+
+        Ok(order_by)
+    }
+
+    fn order_by_item(&mut self) -> Result<OrderBy, QueryParserError> {
+        let column = self.value()?;
+
+        let order = if self.eat_keyword("DESC") {
+            Order::Desc
+        } else {
+            self.eat_keyword("ASC");
+            Order::Asc
+        };
+
+        Ok(OrderBy { column, order })
+    }
+
+    fn limit(&mut self) -> Result<usize, QueryParserError> {
+        let token = self.advance();
+
+        match &token.kind {
+            TokenKind::Word(word) => match word.parse::<usize>() {
+                Ok(limit) if limit > 0 => Ok(limit),
+                _ => Err(unexpected("positive number", &token)),
+            },
+            _ => Err(unexpected("positive number", &token)),
+        }
+    }
+
+    fn bucket(&mut self) -> Result<TimeDelta, QueryParserError> {
+        let (input, location) = self.raw_text("bucket duration", |token| {
+            CLAUSE_KEYWORDS
+                .iter()
+                .any(|keyword| token.is_keyword(keyword))
+        })?;
+
+        BucketParser::from_str(&input)
+            .map_err(|e| QueryParserError::InvalidTime(format!("{} '{}'", e, input), location))
+    }
+
+    fn correlate(&mut self) -> Result<Correlate, QueryParserError> {
+        self.eat_keyword("WITH");
+
+        let data_source = self.data_source().map_err(|e| match e {
+            QueryParserError::InvalidFrom(msg, location) => {
+                QueryParserError::InvalidCorrelate(msg, location)
+            }
+            e => e,
+        })?;
+
+        self.expect_keyword("ON")?;
+
+        let mut conditions: Vec<Condition> = Vec::new();
+        let mut dependent_conditions: Vec<CorrelateCondition> = Vec::new();
+
+        // IS/WITHIN dependencies are only allowed as top-level AND operands, so
+        // the AND chain is parsed here and regular conditions are delegated.
+        loop {
+            let operator = self.peek_nth(1);
+
+            if operator.is_keyword("IS") || operator.is_keyword("WITHIN") {
+                dependent_conditions.push(self.correlate_condition(&data_source)?);
             } else {
-                Self::handle_conditions(condition, &mut query_input)?;
+                conditions.push(self.not_condition()?);
+            }
+
+            if !self.eat_keyword("AND") {
+                break;
             }
         }
 
-        let correlate = Correlate {
+        if self.peek().is_keyword("OR") {
+            let or = self.advance();
+
+            if !dependent_conditions.is_empty() {
+                return Err(QueryParserError::InvalidCorrelate(
+                    "OR cannot be combined with IS or WITHIN, wrap the OR conditions in parentheses"
+                        .to_string(),
+                    or.location,
+                ));
+            }
+
+            let left = conditions
+                .into_iter()
+                .reduce(|left, right| Condition::And(Box::new(left), Box::new(right)))
+                .expect("at least one condition is parsed before OR");
+
+            conditions = vec![Condition::Or(
+                Box::new(left),
+                Box::new(self.or_condition()?),
+            )];
+        }
+
+        let query_input = QueryInput {
+            conditions,
+            ..QueryInput::default()
+        };
+
+        Ok(Correlate {
             data_source,
             query_input: Box::new(query_input),
             dependent_conditions,
+        })
+    }
+
+    /// `<id>.<column> IS <id>.<column>` or `<id>.<column> WITHIN <duration> OF <id>.<column>`
+    fn correlate_condition(
+        &mut self,
+        data_source: &DataSource,
+    ) -> Result<CorrelateCondition, QueryParserError> {
+        let (field, location) = self.word("correlated column")?;
+        let (first_id, first_col) = self.correlate_field(&field, location)?;
+
+        let delta = if self.eat_keyword("WITHIN") {
+            let (duration, duration_location) =
+                self.raw_text("duration", |token| token.is_keyword("OF"))?;
+
+            let delta = DurationParser::from_str(&duration).map_err(|e| {
+                QueryParserError::InvalidCorrelate(
+                    format!("Invalid duration '{}': {}", duration, e),
+                    duration_location,
+                )
+            })?;
+
+            self.expect_keyword("OF")?;
+
+            Some(delta)
+        } else {
+            self.expect_keyword("IS")?;
+
+            None
         };
 
-        input.correlate = Some(correlate);
-        Ok(())
-    }
+        let (other, location) = self.word("correlated column")?;
+        let (_, other_col) = self.correlate_field(&other, location)?;
 
-    fn handle_correlate_field(field_str: &str) -> Result<[&str; 2], QueryParserError> {
-        let field_str = field_str.trim();
+        let (parent, child) = if first_id == data_source.id {
+            (other_col, first_col)
+        } else {
+            (first_col, other_col)
+        };
 
-        let id_and_column = field_str.split('.').collect::<Vec<&str>>();
+        Ok(match delta {
+            Some(delta) => CorrelateCondition::Within {
+                parent,
+                child,
+                delta,
+            },
+            None => CorrelateCondition::Is { parent, child },
+        })
+    }
 
-        if id_and_column.len() != 2 {
+    /// Splits a `<data source id>.<column>` reference and checks that the data source exists.
+    fn correlate_field(
+        &self,
+        field: &str,
+        location: Location,
+    ) -> Result<(String, Column), QueryParserError> {
+        let Some((data_source_id, column)) = field.split_once('.') else {
             return Err(QueryParserError::InvalidCorrelate(
-                "Invalid field".to_string(),
+                format!("Expected <data source id>.<column>, found '{}'", field),
+                location,
             ));
+        };
+
+        if self.find_data_source(data_source_id).is_none() {
+            return Err(QueryParserError::UnknownDataSource(
+                data_source_id.to_string(),
+                location,
+            ));
+        }
+
+        Ok((data_source_id.to_string(), column.to_string()))
+    }
+
+    /// Returns the source text of the tokens up to (not including) the first token
+    /// matching `is_end`, or the value of a single quoted string.
+    fn raw_text(
+        &mut self,
+        expected: &str,
+        is_end: impl Fn(&Token) -> bool,
+    ) -> Result<(String, Location), QueryParserError> {
+        let first = self.peek().clone();
+
+        if let TokenKind::Str(value) = first.kind {
+            self.advance();
+            return Ok((value, first.location));
+        }
+
+        let mut end = first.location.start;
+
+        while self.peek().kind != TokenKind::Eof && !is_end(self.peek()) {
+            end = self.advance().location.end;
+        }
+
+        if end == first.location.start {
+            return Err(unexpected(expected, &first));
+        }
+
+        Ok((
+            self.source[first.location.start..end].to_string(),
+            first.location,
+        ))
+    }
+
+    fn word(&mut self, expected: &str) -> Result<(String, Location), QueryParserError> {
+        let token = self.advance();
+
+        match token.kind {
+            TokenKind::Word(word) => Ok((word, token.location)),
+            _ => Err(unexpected(expected, &token)),
+        }
+    }
+
+    fn expect(&mut self, kind: &TokenKind) -> Result<Token, QueryParserError> {
+        let token = self.advance();
+
+        if &token.kind == kind {
+            Ok(token)
+        } else {
+            Err(unexpected(&kind.to_string(), &token))
+        }
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<Token, QueryParserError> {
+        let token = self.advance();
+
+        if token.is_keyword(keyword) {
+            Ok(token)
+        } else {
+            Err(unexpected(keyword, &token))
+        }
+    }
+
+    fn eat(&mut self, kind: &TokenKind) -> bool {
+        if &self.peek().kind == kind {
+            self.advance();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        if self.peek().is_keyword(keyword) {
+            self.advance();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn peek(&self) -> &Token {
+        &self.tokens[self.position]
+    }
+
+    fn peek_nth(&self, n: usize) -> &Token {
+        &self.tokens[(self.position + n).min(self.tokens.len() - 1)]
+    }
+
+    /// Consumes the current token. The trailing `Eof` token is never consumed.
+    fn advance(&mut self) -> Token {
+        let token = self.tokens[self.position].clone();
+
+        if token.kind != TokenKind::Eof {
+            self.position += 1;
         }
 
-        let data_source_id = id_and_column[0];
-        let column = id_and_column[1];
+        token
+    }
+}
+
+fn unexpected(expected: &str, found: &Token) -> QueryParserError {
+    QueryParserError::UnexpectedToken {
+        expected: expected.to_string(),
+        found: found.kind.to_string(),
+        location: found.location,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{DataSourceDetails, DataSourceType, NewRelicLog};
+
+    use super::*;
+
+    fn data_sources() -> Vec<DataSource> {
+        vec![
+            DataSource {
+                id: "alb".to_string(),
+                ..DataSource::default()
+            },
+            DataSource {
+                id: "nr".to_string(),
+                source_type: DataSourceType::NewRelicLog,
+                details: DataSourceDetails::NewRelicLog(NewRelicLog {
+                    api_key: String::new(),
+                    account_id: String::new(),
+                    table: "Log".to_string(),
+                }),
+                ..DataSource::default()
+            },
+        ]
+    }
+
+    fn parse(query: &str) -> Result<QueryParserOutput, QueryParserError> {
+        QueryParser::parse_with(query, &data_sources())
+    }
+
+    fn conditions(query: &str) -> Vec<Condition> {
+        parse(query).unwrap().0.conditions
+    }
+
+    fn equals(column: &str, value: &str) -> Condition {
+        Where::Equals(column.to_string(), value.to_string()).into()
+    }
+
+    #[test]
+    fn quoted_values_may_contain_keywords_and_spaces() {
+        assert_eq!(
+            conditions("SELECT * FROM alb WHERE a = 'x AND y' AND b = \"ON z\" AND c = 'it''s' AND d = 'it\\'s'"),
+            vec![Condition::And(
+                Box::new(Condition::And(
+                    Box::new(Condition::And(
+                        Box::new(equals("a", "x AND y")),
+                        Box::new(equals("b", "ON z"))
+                    )),
+                    Box::new(equals("c", "it's"))
+                )),
+                Box::new(equals("d", "it's"))
+            )]
+        );
+    }
+
+    #[test]
+    fn keywords_are_case_insensitive() {
+        let (input, from) =
+            parse("select count(*) from alb where a = 1 facet b order by count desc limit 5")
+                .unwrap();
+
+        assert_eq!(from, vec![data_sources()[0].clone()]);
+        assert_eq!(input.select, vec![Select::Count(None)]);
+        assert_eq!(input.conditions, vec![equals("a", "1")]);
+        assert_eq!(input.facet, vec![Facet("b".to_string())]);
+        assert_eq!(
+            input.order_by,
+            vec![OrderBy {
+                column: "count".to_string(),
+                order: Order::Desc,
+            }]
+        );
+        assert_eq!(input.limit, Some(5));
+    }
+
+    #[test]
+    fn unexpected_token_is_located() {
+        let Err(QueryParserError::UnexpectedToken {
+            expected,
+            found,
+            location,
+        }) = parse("SELECT *\nFROM alb\nWHERE a = 1 AND = 2")
+        else {
+            panic!("expected an unexpected token");
+        };
+
+        assert_eq!(expected, "column, NOT or '('");
+        assert_eq!(found, "'='");
+        assert_eq!((location.line, location.column), (3, 17));
+    }
+
+    #[test]
+    fn unexpected_end_of_query_is_located() {
+        let Err(QueryParserError::UnexpectedToken {
+            found, location, ..
+        }) = parse("SELECT * FROM alb WHERE a =")
+        else {
+            panic!("expected an unexpected token");
+        };
+
+        assert_eq!(found, "end of query");
+        assert_eq!((location.line, location.column), (1, 28));
+    }
+
+    #[test]
+    fn lexer_errors_are_located() {
+        let error = parse("SELECT * FROM alb\nWHERE a = 'x").unwrap_err();
+
+        assert!(matches!(error, QueryParserError::UnterminatedString(_)));
+        assert_eq!((error.location().line, error.location().column), (2, 11));
+
+        let error = parse("SELECT * FROM alb WHERE a ! 1").unwrap_err();
+
+        assert!(matches!(
+            error,
+            QueryParserError::UnexpectedCharacter('!', _)
+        ));
+        assert_eq!((error.location().line, error.location().column), (1, 27));
+    }
+
+    #[test]
+    fn unknown_data_source_is_located() {
+        let error = parse("SELECT * FROM albb").unwrap_err();
+
+        assert!(matches!(error, QueryParserError::UnknownDataSource(ref id, _) if id == "albb"));
+        assert_eq!(error.location().column, 15);
+    }
+
+    #[test]
+    fn correlate_matches_the_previous_parser() {
+        let (input, _) = parse(
+            "SELECT * FROM alb CORRELATE WITH nr ON alb.client_ip IS nr.clientip AND alb.time WITHIN 5m OF nr.timestamp",
+        )
+        .unwrap();
+
+        assert_eq!(
+            input.correlate,
+            Some(Correlate {
+                data_source: data_sources()[1].clone(),
+                query_input: Box::default(),
+                dependent_conditions: vec![
+                    CorrelateCondition::Is {
+                        parent: "client_ip".to_string(),
+                        child: "clientip".to_string(),
+                    },
+                    CorrelateCondition::Within {
+                        parent: "time".to_string(),
+                        child: "timestamp".to_string(),
+                        delta: TimeDelta::minutes(5),
+                    },
+                ],
+            })
+        );
+    }
+
+    #[test]
+    fn correlate_orders_fields_by_data_source() {
+        let (input, _) = parse(
+            "SELECT * FROM alb CORRELATE nr ON nr.clientip IS alb.client_ip AND message LIKE '%x%'",
+        )
+        .unwrap();
+
+        let correlate = input.correlate.unwrap();
+
+        assert_eq!(
+            correlate.dependent_conditions,
+            vec![CorrelateCondition::Is {
+                parent: "client_ip".to_string(),
+                child: "clientip".to_string(),
+            }]
+        );
+        assert_eq!(
+            correlate.query_input.conditions,
+            vec![Condition::Where(Where::Like(
+                "message".to_string(),
+                "%x%".to_string()
+            ))]
+        );
+    }
+
+    fn render(condition: &Condition) -> String {
+        condition
+            .render(&|condition: &Where| -> Result<String, ()> {
+                Ok(match condition {
+                    Where::Equals(column, value) => format!("{} = {}", column, value),
+                    Where::In(column, values) => format!("{} IN ({})", column, values.join(", ")),
+                    _ => unreachable!(),
+                })
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        let conditions = conditions("SELECT * FROM alb WHERE a = 1 AND b = 2 OR c = 3");
+
+        assert_eq!(
+            conditions,
+            vec![Condition::Or(
+                Box::new(Condition::And(
+                    Box::new(equals("a", "1")),
+                    Box::new(equals("b", "2"))
+                )),
+                Box::new(equals("c", "3"))
+            )]
+        );
+        assert_eq!(render(&conditions[0]), "(a = 1 AND b = 2 OR c = 3)");
+    }
+
+    #[test]
+    fn or_inside_and_keeps_its_parentheses() {
+        let conditions = conditions("SELECT * FROM alb WHERE a = 1 AND (b = 2 OR c = 3)");
+
+        assert_eq!(render(&conditions[0]), "a = 1 AND (b = 2 OR c = 3)");
+    }
+
+    #[test]
+    fn not_applies_to_the_group() {
+        let conditions = conditions("SELECT * FROM alb WHERE NOT (a = 1 OR b = 2) AND c = 3");
+
+        assert_eq!(
+            conditions,
+            vec![Condition::And(
+                Box::new(Condition::Not(Box::new(Condition::Group(Box::new(
+                    Condition::Or(Box::new(equals("a", "1")), Box::new(equals("b", "2")))
+                ))))),
+                Box::new(equals("c", "3"))
+            )]
+        );
+        assert_eq!(render(&conditions[0]), "NOT (a = 1 OR b = 2) AND c = 3");
+    }
+
+    #[test]
+    fn not_in_negates_the_comparison() {
+        let conditions = conditions("SELECT * FROM alb WHERE x NOT IN (1, 2) OR y = 3");
+
+        assert_eq!(
+            conditions,
+            vec![Condition::Or(
+                Box::new(Condition::Not(Box::new(
+                    Where::In("x".to_string(), vec!["1".to_string(), "2".to_string()]).into()
+                ))),
+                Box::new(equals("y", "3"))
+            )]
+        );
+        assert_eq!(render(&conditions[0]), "(NOT x IN (1, 2) OR y = 3)");
+    }
+
+    #[test]
+    fn correlate_rejects_or_with_dependencies() {
+        let error = parse(
+            "SELECT * FROM alb CORRELATE nr ON alb.client_ip IS nr.clientip AND a = 1 OR b = 2",
+        )
+        .unwrap_err();
+
+        assert!(matches!(error, QueryParserError::InvalidCorrelate(..)));
+        assert_eq!(error.location().column, 74);
+    }
+
+    #[test]
+    fn correlate_allows_or_in_parentheses() {
+        let (input, _) = parse(
+            "SELECT * FROM alb CORRELATE nr ON alb.client_ip IS nr.clientip AND (a = 1 OR b = 2)",
+        )
+        .unwrap();
+
+        let correlate = input.correlate.unwrap();
+
+        assert_eq!(correlate.dependent_conditions.len(), 1);
+        assert_eq!(
+            render(&correlate.query_input.conditions[0]),
+            "(a = 1 OR b = 2)"
+        );
+    }
 
-        DatasetParser::from_id(data_source_id)
-            .ok_or(QueryParserError::InvalidCorrelate(
-                "Invalid data source id".to_string(),
-            ))
-            .unwrap();
+    #[test]
+    fn correlate_allows_or_without_dependencies() {
+        let (input, _) =
+            parse("SELECT * FROM alb CORRELATE nr ON a = 1 AND b = 2 OR c = 3").unwrap();
 
-        Ok([data_source_id, column])
+        assert_eq!(
+            input.correlate.unwrap().query_input.conditions,
+            conditions("SELECT * FROM alb WHERE a = 1 AND b = 2 OR c = 3")
+        );
     }
 }
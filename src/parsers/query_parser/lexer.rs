@@ -0,0 +1,352 @@
+use std::fmt;
+
+use super::QueryParserError;
+
+/// Position of a token in the query string. `line` and `column` are 1-based,
+/// `start` and `end` are byte offsets into the source.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Location {
+    pub line: usize,
+    pub column: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.column)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum TokenKind {
+    /// Unquoted run of characters, e.g. keywords, columns, numbers or bare values
+    Word(String),
+    /// Quoted string literal with the quotes removed and escapes resolved
+    Str(String),
+    Comma,
+    LeftParen,
+    RightParen,
+    Equals,
+    NotEquals,
+    LessThan,
+    LessThanOrEqual,
+    GreaterThan,
+    GreaterThanOrEqual,
+    Eof,
+}
+
+impl fmt::Display for TokenKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TokenKind::Word(word) => write!(f, "'{}'", word),
+            TokenKind::Str(value) => write!(f, "string '{}'", value),
+            TokenKind::Comma => write!(f, "','"),
+            TokenKind::LeftParen => write!(f, "'('"),
+            TokenKind::RightParen => write!(f, "')'"),
+            TokenKind::Equals => write!(f, "'='"),
+            TokenKind::NotEquals => write!(f, "'!='"),
+            TokenKind::LessThan => write!(f, "'<'"),
+            TokenKind::LessThanOrEqual => write!(f, "'<='"),
+            TokenKind::GreaterThan => write!(f, "'>'"),
+            TokenKind::GreaterThanOrEqual => write!(f, "'>='"),
+            TokenKind::Eof => write!(f, "end of query"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub location: Location,
+}
+
+impl Token {
+    /// Case-insensitive keyword check. Quoted strings are never keywords.
+    pub fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(&self.kind, TokenKind::Word(word) if word.eq_ignore_ascii_case(keyword))
+    }
+}
+
+pub struct Lexer<'a> {
+    source: &'a str,
+    chars: std::iter::Peekable<std::str::CharIndices<'a>>,
+    line: usize,
+    column: usize,
+}
+
+impl<'a> Lexer<'a> {
+    pub fn new(source: &'a str) -> Self {
+        Self {
+            source,
+            chars: source.char_indices().peekable(),
+            line: 1,
+            column: 1,
+        }
+    }
+
+    pub fn tokenize(mut self) -> Result<Vec<Token>, QueryParserError> {
+        let mut tokens = Vec::new();
+
+        loop {
+            let token = self.next_token()?;
+            let is_eof = token.kind == TokenKind::Eof;
+
+            tokens.push(token);
+
+            if is_eof {
+                return Ok(tokens);
+            }
+        }
+    }
+
+    fn next_token(&mut self) -> Result<Token, QueryParserError> {
+        while self.chars.peek().is_some_and(|(_, c)| c.is_whitespace()) {
+            self.bump();
+        }
+
+        let (line, column) = (self.line, self.column);
+
+        let Some((start, c)) = self.bump() else {
+            return Ok(Token {
+                kind: TokenKind::Eof,
+                location: Location {
+                    line,
+                    column,
+                    start: self.source.len(),
+                    end: self.source.len(),
+                },
+            });
+        };
+
+        let kind = match c {
+            ',' => TokenKind::Comma,
+            '(' => TokenKind::LeftParen,
+            ')' => TokenKind::RightParen,
+            '=' => TokenKind::Equals,
+            '!' if self.bump_if('=') => TokenKind::NotEquals,
+            '<' if self.bump_if('=') => TokenKind::LessThanOrEqual,
+            '<' if self.bump_if('>') => TokenKind::NotEquals,
+            '<' => TokenKind::LessThan,
+            '>' if self.bump_if('=') => TokenKind::GreaterThanOrEqual,
+            '>' => TokenKind::GreaterThan,
+            '\'' | '"' => TokenKind::Str(self.string(c, line, column, start)?),
+            _ if is_word_char(c) => {
+                while self.chars.peek().is_some_and(|&(_, c)| is_word_char(c)) {
+                    self.bump();
+                }
+
+                TokenKind::Word(self.source[start..self.offset()].to_string())
+            }
+            _ => {
+                return Err(QueryParserError::UnexpectedCharacter(
+                    c,
+                    Location {
+                        line,
+                        column,
+                        start,
+                        end: self.offset(),
+                    },
+                ))
+            }
+        };
+
+        Ok(Token {
+            kind,
+            location: Location {
+                line,
+                column,
+                start,
+                end: self.offset(),
+            },
+        })
+    }
+
+    /// Reads a string literal up to the closing `quote`. A doubled quote
+    /// (`'it''s'`) and a backslash escape (`'it\'s'`) both produce a literal quote.
+    fn string(
+        &mut self,
+        quote: char,
+        line: usize,
+        column: usize,
+        start: usize,
+    ) -> Result<String, QueryParserError> {
+        let mut value = String::new();
+
+        loop {
+            match self.bump() {
+                Some((_, c)) if c == quote => {
+                    if self.bump_if(quote) {
+                        value.push(quote);
+                    } else {
+                        return Ok(value);
+                    }
+                }
+                Some((_, '\\')) => match self.bump() {
+                    Some((_, escaped)) => value.push(escaped),
+                    None => break,
+                },
+                Some((_, c)) => value.push(c),
+                None => break,
+            }
+        }
+
+        Err(QueryParserError::UnterminatedString(Location {
+            line,
+            column,
+            start,
+            end: self.source.len(),
+        }))
+    }
+
+    fn bump(&mut self) -> Option<(usize, char)> {
+        let (offset, c) = self.chars.next()?;
+
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+
+        Some((offset, c))
+    }
+
+    fn bump_if(&mut self, expected: char) -> bool {
+        if self.chars.peek().is_some_and(|&(_, c)| c == expected) {
+            self.bump();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn offset(&mut self) -> usize {
+        self.chars
+            .peek()
+            .map(|&(offset, _)| offset)
+            .unwrap_or(self.source.len())
+    }
+}
+
+fn is_word_char(c: char) -> bool {
+    !c.is_whitespace() && !matches!(c, ',' | '(' | ')' | '=' | '!' | '<' | '>' | '\'' | '"')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(source: &str) -> Vec<TokenKind> {
+        Lexer::new(source)
+            .tokenize()
+            .unwrap()
+            .into_iter()
+            .map(|token| token.kind)
+            .collect()
+    }
+
+    fn word(word: &str) -> TokenKind {
+        TokenKind::Word(word.to_string())
+    }
+
+    fn string(value: &str) -> TokenKind {
+        TokenKind::Str(value.to_string())
+    }
+
+    #[test]
+    fn splits_words_and_operators() {
+        assert_eq!(
+            kinds("a>=1 AND b!=2,c<>3 (d<=4)"),
+            vec![
+                word("a"),
+                TokenKind::GreaterThanOrEqual,
+                word("1"),
+                word("AND"),
+                word("b"),
+                TokenKind::NotEquals,
+                word("2"),
+                TokenKind::Comma,
+                word("c"),
+                TokenKind::NotEquals,
+                word("3"),
+                TokenKind::LeftParen,
+                word("d"),
+                TokenKind::LessThanOrEqual,
+                word("4"),
+                TokenKind::RightParen,
+                TokenKind::Eof,
+            ]
+        );
+    }
+
+    #[test]
+    fn quoted_strings_keep_keywords_and_spaces() {
+        assert_eq!(
+            kinds("'a AND b' \"ON x\""),
+            vec![string("a AND b"), string("ON x"), TokenKind::Eof]
+        );
+    }
+
+    #[test]
+    fn quoted_strings_resolve_escapes() {
+        assert_eq!(
+            kinds(r#"'it''s' 'it\'s' "say ""hi""""#),
+            vec![
+                string("it's"),
+                string("it's"),
+                string("say \"hi\""),
+                TokenKind::Eof
+            ]
+        );
+    }
+
+    #[test]
+    fn keywords_are_case_insensitive() {
+        let tokens = Lexer::new("select 'select'").tokenize().unwrap();
+
+        assert!(tokens[0].is_keyword("SELECT"));
+        assert!(!tokens[1].is_keyword("SELECT"));
+    }
+
+    #[test]
+    fn locations_are_line_and_column() {
+        let tokens = Lexer::new("SELECT *\n  FROM alb").tokenize().unwrap();
+
+        assert_eq!(
+            tokens[2].location,
+            Location {
+                line: 2,
+                column: 3,
+                start: 11,
+                end: 15,
+            }
+        );
+        assert_eq!(tokens[4].location.line, 2);
+        assert_eq!(tokens[4].location.column, 11);
+    }
+
+    #[test]
+    fn unterminated_string_is_located_at_the_quote() {
+        let Err(QueryParserError::UnterminatedString(location)) =
+            Lexer::new("a = 1\nAND b = 'x").tokenize()
+        else {
+            panic!("expected an unterminated string");
+        };
+
+        assert_eq!((location.line, location.column), (2, 9));
+        assert_eq!((location.start, location.end), (14, 16));
+    }
+
+    #[test]
+    fn unexpected_character_is_located() {
+        let Err(QueryParserError::UnexpectedCharacter(c, location)) =
+            Lexer::new("a = 1 !b").tokenize()
+        else {
+            panic!("expected an unexpected character");
+        };
+
+        assert_eq!(c, '!');
+        assert_eq!((location.line, location.column), (1, 7));
+    }
+}
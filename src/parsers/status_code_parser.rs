@@ -0,0 +1,103 @@
+use regex::Regex;
+use std::ops::{Deref, RangeInclusive};
+
+use super::Parser;
+
+/// Inclusive range of HTTP status codes. Accepts single codes (`502`), classes
+/// (`5xx`, `50x`) and ranges (`500-504`).
+#[derive(Debug, Clone)]
+pub struct StatusCodeParser(RangeInclusive<u16>);
+
+impl Deref for StatusCodeParser {
+    type Target = RangeInclusive<u16>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl Parser for StatusCodeParser {
+    type Output = RangeInclusive<u16>;
+
+    fn from_str(input: &str) -> Result<RangeInclusive<u16>, &'static str> {
+        let input = input.trim();
+
+        let re = Regex::new(r"(?i)^([1-5])(\d|x)(\d|x)$").unwrap();
+
+        if let Some(caps) = re.captures(input) {
+            let wildcards = [&caps[2], &caps[3]];
+
+            // Wildcards are only allowed as a suffix, e.g. 5xx or 50x but not 5x0
+            if !wildcards[0].eq_ignore_ascii_case("x") || wildcards[1].eq_ignore_ascii_case("x") {
+                let start = input
+                    .to_lowercase()
+                    .replace('x', "0")
+                    .parse::<u16>()
+                    .unwrap();
+                let end = input
+                    .to_lowercase()
+                    .replace('x', "9")
+                    .parse::<u16>()
+                    .unwrap();
+
+                return Ok(start..=end);
+            }
+        }
+
+        let re = Regex::new(r"^([1-5]\d\d)\s*-\s*([1-5]\d\d)$").unwrap();
+
+        if let Some(caps) = re.captures(input) {
+            let start: u16 = caps[1].parse().unwrap();
+            let end: u16 = caps[2].parse().unwrap();
+
+            if start <= end {
+                return Ok(start..=end);
+            }
+        }
+
+        Err("Could not parse status code, expected e.g. 502, 5xx, 50x or 500-504")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_single_codes() {
+        assert_eq!(StatusCodeParser::from_str("502"), Ok(502..=502));
+    }
+
+    #[test]
+    fn parses_classes() {
+        assert_eq!(StatusCodeParser::from_str("5xx"), Ok(500..=599));
+        assert_eq!(StatusCodeParser::from_str("4XX"), Ok(400..=499));
+        assert_eq!(StatusCodeParser::from_str("50x"), Ok(500..=509));
+    }
+
+    #[test]
+    fn parses_ranges() {
+        assert_eq!(StatusCodeParser::from_str("500-504"), Ok(500..=504));
+        assert_eq!(StatusCodeParser::from_str("500 - 504"), Ok(500..=504));
+        assert_eq!(StatusCodeParser::from_str("500-500"), Ok(500..=500));
+    }
+
+    #[test]
+    fn rejects_wildcards_before_digits() {
+        assert!(StatusCodeParser::from_str("5x0").is_err());
+        assert!(StatusCodeParser::from_str("x00").is_err());
+    }
+
+    #[test]
+    fn rejects_reversed_ranges() {
+        assert!(StatusCodeParser::from_str("504-500").is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_codes() {
+        assert!(StatusCodeParser::from_str("600").is_err());
+        assert!(StatusCodeParser::from_str("5x").is_err());
+        assert!(StatusCodeParser::from_str("5000").is_err());
+        assert!(StatusCodeParser::from_str("abc").is_err());
+    }
+}
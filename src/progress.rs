@@ -0,0 +1,79 @@
+use std::{
+    io::{stderr, IsTerminal, Write},
+    time::Instant,
+};
+
+use log::{info, LevelFilter};
+
+/// Status line of a running query on stderr. On a terminal the line is rewritten
+/// in place, otherwise a line is logged whenever the state changes. Verbose logs
+/// would break up the line, so they get a line per state as well.
+pub struct Progress<'a> {
+    label: &'a str,
+    started: Instant,
+    interactive: bool,
+    state: Option<String>,
+}
+
+impl<'a> Progress<'a> {
+    pub fn new(label: &'a str) -> Self {
+        Self {
+            label,
+            started: Instant::now(),
+            interactive: stderr().is_terminal() && log::max_level() == LevelFilter::Info,
+            state: None,
+        }
+    }
+
+    pub fn update(&mut self, state: &str, bytes_scanned: Option<i64>) {
+        if !self.interactive && self.state.as_deref() == Some(state) {
+            return;
+        }
+
+        let mut line = format!(
+            "[{}] {} {:.1}s",
+            self.label,
+            state,
+            self.started.elapsed().as_secs_f64()
+        );
+
+        if let Some(bytes) = bytes_scanned {
+            line.push_str(&format!(", {} scanned", format_bytes(bytes)));
+        }
+
+        if self.interactive {
+            eprint!("\r\x1b[2K{}", line);
+            let _ = stderr().flush();
+        } else {
+            info!("{}", line);
+        }
+
+        self.state = Some(state.to_string());
+    }
+
+    /// Ends the line rewritten in place
+    pub fn finish(&mut self) {
+        if self.interactive && self.state.take().is_some() {
+            eprintln!();
+        }
+    }
+}
+
+/// Binary units, e.g. `1.5 GiB`
+pub fn format_bytes(bytes: i64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+
+    let mut value = bytes as f64;
+    let mut unit = 0;
+
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
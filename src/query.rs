@@ -1,14 +1,25 @@
-use std::{collections::HashMap, fmt};
+use std::{cmp::Ordering, collections::HashMap, fmt, time::Duration};
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::parsers::Where;
 
 #[derive(Debug)]
 pub enum QueryError {
-    UnknownColumn(String),
+    /// The column and the most similar known column
+    UnknownColumn(String, Option<String>),
+    InvalidAggregate(String),
+    InvalidValue(String),
 }
 
 impl fmt::Display for QueryError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            QueryError::UnknownColumn(msg) => write!(f, "Unknown column: {}", msg),
+            QueryError::UnknownColumn(column, _) => write!(f, "Unknown column: {}", column),
+            QueryError::InvalidAggregate(msg) => write!(f, "Invalid aggregate: {}", msg),
+            QueryError::InvalidValue(msg) => write!(f, "Invalid value: {}", msg),
         }
     }
 }
@@ -17,21 +28,27 @@ impl std::error::Error for QueryError {}
 
 #[derive(Debug)]
 pub enum QueryExecutionError {
-    QueryTimeout,
+    QueryTimeout(Duration),
     BadQueryStatus(String),
     NoData,
     ClientError(String),
     ParseError(String),
+    Unsupported(String),
 }
 
 impl fmt::Display for QueryExecutionError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            QueryExecutionError::QueryTimeout => write!(f, "Query is taking too long. Aborting!"),
+            QueryExecutionError::QueryTimeout(timeout) => write!(
+                f,
+                "Query did not finish within {}s and was stopped, raise --timeout to wait longer",
+                timeout.as_secs()
+            ),
             QueryExecutionError::BadQueryStatus(msg) => write!(f, "Bad query status: {}", msg),
             QueryExecutionError::NoData => write!(f, "No data found"),
             QueryExecutionError::ClientError(msg) => write!(f, "Client error: {}", msg),
             QueryExecutionError::ParseError(msg) => write!(f, "Parse error: {}", msg),
+            QueryExecutionError::Unsupported(msg) => write!(f, "Not supported: {}", msg),
         }
     }
 }
@@ -39,3 +56,90 @@ impl fmt::Display for QueryExecutionError {
 impl std::error::Error for QueryExecutionError {}
 
 pub type QueryResult = Vec<HashMap<String, serde_json::Value>>;
+
+/// Rows of a query, `truncated` if the row cap stopped reading them
+#[derive(Debug, Default)]
+pub struct QueryOutput {
+    pub rows: QueryResult,
+    pub truncated: bool,
+    pub statistics: Option<QueryStatistics>,
+}
+
+/// What a data source reports about running a query, Athena bills by the bytes scanned
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct QueryStatistics {
+    pub data_scanned_bytes: Option<i64>,
+    pub engine_time_ms: Option<i64>,
+    pub queue_time_ms: Option<i64>,
+}
+
+/// How the data source plans to run a query. `estimated_scan` is the size the
+/// planner expects to read, `statistics` are set if the query actually ran.
+#[derive(Debug, Default)]
+pub struct QueryPlan {
+    pub plan: String,
+    pub estimated_scan: Option<String>,
+    pub statistics: Option<QueryStatistics>,
+}
+
+/// Column of a data source as reported by `fivexx schema`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SchemaColumn {
+    pub name: String,
+    pub data_type: String,
+    pub partition: bool,
+}
+
+/// Accepts epoch milliseconds (New Relic) and the UTC timestamp formats of the adapters
+pub fn parse_timestamp(value: &Value) -> Option<DateTime<Utc>> {
+    match value {
+        Value::Number(n) => DateTime::from_timestamp_millis(n.as_f64()? as i64),
+        Value::String(s) => NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S%.fZ")
+            .or_else(|_| NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S%.f UTC"))
+            .or_else(|_| NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S%.f"))
+            .map(|datetime| datetime.and_utc())
+            .ok(),
+        _ => None,
+    }
+}
+
+/// Compares numerically when both values are numbers or numeric strings, otherwise
+/// as strings. Missing values and nulls are equal to each other and sort first.
+pub fn compare_values(a: Option<&Value>, b: Option<&Value>) -> Ordering {
+    let number = |value: &Value| value.as_f64().or_else(|| value.as_str()?.parse().ok());
+
+    match (a.filter(|a| !a.is_null()), b.filter(|b| !b.is_null())) {
+        (None, None) => Ordering::Equal,
+        (None, Some(_)) => Ordering::Less,
+        (Some(_), None) => Ordering::Greater,
+        (Some(a), Some(b)) => match (number(a), number(b)) {
+            (Some(a), Some(b)) => a.total_cmp(&b),
+            _ => match (a, b) {
+                (Value::String(a), Value::String(b)) => a.cmp(b),
+                (a, b) => a.to_string().cmp(&b.to_string()),
+            },
+        },
+    }
+}
+
+/// Evaluates a HAVING comparison against a result row. Missing aggregates never match.
+pub fn having_matches(row: &HashMap<String, Value>, condition: &Where) -> bool {
+    let Some(actual) = row.get(condition.column()).filter(|value| !value.is_null()) else {
+        return false;
+    };
+
+    let compare =
+        |value: &str| compare_values(Some(actual), Some(&Value::String(value.to_string())));
+
+    match condition {
+        Where::Equals(_, value) => compare(value).is_eq(),
+        Where::NotEquals(_, value) => compare(value).is_ne(),
+        Where::In(_, values) => values.iter().any(|value| compare(value).is_eq()),
+        Where::GreaterThan(_, value) => compare(value).is_gt(),
+        Where::LessThan(_, value) => compare(value).is_lt(),
+        Where::GreaterThanOrEqual(_, value) => compare(value).is_ge(),
+        Where::LessThanOrEqual(_, value) => compare(value).is_le(),
+        // Rejected by the parser for HAVING
+        Where::Like(..) => false,
+    }
+}
@@ -0,0 +1,96 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        LazyLock, Mutex,
+    },
+};
+
+use aws_sdk_athena::Client as AthenaClient;
+use futures::future::join_all;
+use log::{error, info};
+
+/// Queries that were started and haven't finished yet, so they can be stopped when
+/// fivexx is interrupted. New Relic queries run synchronously in NerdGraph and can't
+/// be stopped, so they aren't tracked.
+pub static RUNNING_QUERIES: LazyLock<RunningQueries> = LazyLock::new(RunningQueries::default);
+
+pub enum RunningQuery {
+    /// Keeps running (and billing) on Athena until it is stopped
+    Athena {
+        client: AthenaClient,
+        query_execution_id: String,
+    },
+}
+
+impl RunningQuery {
+    pub async fn stop(&self) {
+        match self {
+            RunningQuery::Athena {
+                client,
+                query_execution_id,
+            } => {
+                let stopped = client
+                    .stop_query_execution()
+                    .query_execution_id(query_execution_id)
+                    .send()
+                    .await;
+
+                match stopped {
+                    Ok(_) => info!("Stopped Athena query {}", query_execution_id),
+                    Err(e) => {
+                        error!("Failed to stop Athena query {}: {}", query_execution_id, e)
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct RunningQueries {
+    next_key: AtomicU64,
+    queries: Mutex<HashMap<u64, RunningQuery>>,
+}
+
+impl RunningQueries {
+    /// Tracks `query` until the returned guard is dropped
+    pub fn register(&self, query: RunningQuery) -> RunningQueryGuard<'_> {
+        let key = self.next_key.fetch_add(1, Ordering::Relaxed);
+
+        self.queries.lock().unwrap().insert(key, query);
+
+        RunningQueryGuard { queries: self, key }
+    }
+
+    /// Stops every query that is still running
+    pub async fn stop_all(&self) {
+        let queries = std::mem::take(&mut *self.queries.lock().unwrap());
+
+        join_all(queries.values().map(RunningQuery::stop)).await;
+    }
+
+    fn remove(&self, key: u64) -> Option<RunningQuery> {
+        self.queries.lock().unwrap().remove(&key)
+    }
+}
+
+pub struct RunningQueryGuard<'a> {
+    queries: &'a RunningQueries,
+    key: u64,
+}
+
+impl RunningQueryGuard<'_> {
+    /// Stops the query, e.g. once the caller gave up waiting for it
+    pub async fn stop(self) {
+        if let Some(query) = self.queries.remove(self.key) {
+            query.stop().await;
+        }
+    }
+}
+
+impl Drop for RunningQueryGuard<'_> {
+    fn drop(&mut self) {
+        self.queries.remove(self.key);
+    }
+}
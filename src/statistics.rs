@@ -0,0 +1,163 @@
+use log::info;
+use serde_json::{json, Value};
+
+use crate::{progress::format_bytes, query::QueryStatistics};
+
+/// On-demand Athena price in USD per TB scanned
+pub const DEFAULT_PRICE_PER_TB: f64 = 5.0;
+
+const MB: i64 = 1 << 20;
+const TB: f64 = (1u64 << 40) as f64;
+
+/// Athena rounds every query up to the next megabyte and bills at least 10 MB
+pub fn estimated_cost(data_scanned_bytes: i64, price_per_tb: f64) -> f64 {
+    let billed = ((data_scanned_bytes + MB - 1) / MB).max(10) * MB;
+
+    billed as f64 / TB * price_per_tb
+}
+
+/// Statistics of the queries of a run, labelled by the data source that ran them
+#[derive(Debug, Default)]
+pub struct RunStatistics {
+    queries: Vec<(String, QueryStatistics)>,
+}
+
+/// The queries of one label added up
+struct Total {
+    queries: usize,
+    statistics: QueryStatistics,
+    estimated_cost: Option<f64>,
+}
+
+impl RunStatistics {
+    pub fn add(&mut self, label: &str, statistics: QueryStatistics) {
+        self.queries.push((label.to_string(), statistics));
+    }
+
+    pub fn extend(&mut self, other: RunStatistics) {
+        self.queries.extend(other.queries);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queries.is_empty()
+    }
+
+    /// Logs one line per label and a total line for several labels
+    pub fn print_summary(&self, price_per_tb: f64) {
+        let totals = self.totals(price_per_tb);
+
+        for (label, total) in &totals {
+            let mut line = format!("[{}]", label);
+
+            if total.queries > 1 {
+                line.push_str(&format!(" {} queries,", total.queries));
+            }
+
+            if let (Some(bytes), Some(cost)) =
+                (total.statistics.data_scanned_bytes, total.estimated_cost)
+            {
+                line.push_str(&format!(" scanned {} (~${:.4})", format_bytes(bytes), cost));
+            }
+
+            if let Some(engine_time) = total.statistics.engine_time_ms {
+                line.push_str(&format!(", engine {:.1}s", engine_time as f64 / 1000.0));
+            }
+
+            if let Some(queue_time) = total.statistics.queue_time_ms {
+                line.push_str(&format!(", queued {:.1}s", queue_time as f64 / 1000.0));
+            }
+
+            info!("{}", line);
+        }
+
+        if totals.len() > 1 {
+            info!(
+                "Total: scanned {} (~${:.4})",
+                format_bytes(self.data_scanned_bytes()),
+                self.estimated_cost(price_per_tb)
+            );
+        }
+    }
+
+    pub fn to_json(&self, price_per_tb: f64) -> Value {
+        let queries = self
+            .totals(price_per_tb)
+            .into_iter()
+            .map(|(label, total)| {
+                json!({
+                    "data_source_id": label,
+                    "queries": total.queries,
+                    "data_scanned_bytes": total.statistics.data_scanned_bytes,
+                    "engine_time_ms": total.statistics.engine_time_ms,
+                    "queue_time_ms": total.statistics.queue_time_ms,
+                    "estimated_cost_usd": total.estimated_cost,
+                })
+            })
+            .collect::<Vec<Value>>();
+
+        json!({
+            "queries": queries,
+            "data_scanned_bytes": self.data_scanned_bytes(),
+            "estimated_cost_usd": self.estimated_cost(price_per_tb),
+            "price_per_tb_usd": price_per_tb,
+        })
+    }
+
+    /// Totals per label in the order the labels were first seen
+    fn totals(&self, price_per_tb: f64) -> Vec<(&str, Total)> {
+        let mut totals: Vec<(&str, Total)> = vec![];
+
+        for (label, statistics) in &self.queries {
+            let cost = statistics
+                .data_scanned_bytes
+                .map(|bytes| estimated_cost(bytes, price_per_tb));
+
+            match totals.iter_mut().find(|(other, _)| other == label) {
+                Some((_, total)) => {
+                    total.queries += 1;
+                    total.statistics.data_scanned_bytes = sum(
+                        total.statistics.data_scanned_bytes,
+                        statistics.data_scanned_bytes,
+                    );
+                    total.statistics.engine_time_ms =
+                        sum(total.statistics.engine_time_ms, statistics.engine_time_ms);
+                    total.statistics.queue_time_ms =
+                        sum(total.statistics.queue_time_ms, statistics.queue_time_ms);
+                    total.estimated_cost = sum(total.estimated_cost, cost);
+                }
+                None => totals.push((
+                    label,
+                    Total {
+                        queries: 1,
+                        statistics: *statistics,
+                        estimated_cost: cost,
+                    },
+                )),
+            }
+        }
+
+        totals
+    }
+
+    fn data_scanned_bytes(&self) -> i64 {
+        self.queries
+            .iter()
+            .filter_map(|(_, statistics)| statistics.data_scanned_bytes)
+            .sum()
+    }
+
+    fn estimated_cost(&self, price_per_tb: f64) -> f64 {
+        self.queries
+            .iter()
+            .filter_map(|(_, statistics)| statistics.data_scanned_bytes)
+            .map(|bytes| estimated_cost(bytes, price_per_tb))
+            .fold(0.0, |total, cost| total + cost)
+    }
+}
+
+fn sum<T: std::ops::Add<Output = T>>(a: Option<T>, b: Option<T>) -> Option<T> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a + b),
+        (a, b) => a.or(b),
+    }
+}
@@ -0,0 +1,429 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use chrono::{DateTime, SecondsFormat, TimeDelta, Utc};
+use serde_json::{json, Value};
+
+use crate::{
+    parsers::{QueryInput, Select},
+    query::{parse_timestamp, QueryResult},
+};
+
+/// Column holding the start of the time bucket of a TIMESERIES row
+pub const BUCKET_COLUMN: &str = "bucket";
+
+/// Upper bound of buckets per series, so a tiny bucket over a long range can't
+/// blow up the result
+const MAX_BUCKETS: i64 = 10_000;
+
+/// Bucket starts are rendered the same for every data source, e.g. `2024-01-01T10:00:00.000Z`
+pub fn format_bucket(start: DateTime<Utc>) -> String {
+    start.to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+/// Adds a row for every bucket a series has no row for and sorts the rows by bucket.
+/// Counts are 0 in the added rows, other aggregates like MAX have no value and are null. A series is a data source together with the values of
+/// the FACET columns, so that all sources line up on the same buckets.
+///
+/// The buckets span SINCE..UNTIL (or now) when given, otherwise the buckets seen.
+pub fn zero_fill(rows: &mut QueryResult, query_input: &QueryInput, data_source_ids: &[String]) {
+    let Some(bucket) = query_input.timeseries else {
+        return;
+    };
+
+    let facets = query_input
+        .facet
+        .iter()
+        .map(|facet| facet.0.clone())
+        .collect::<Vec<String>>();
+
+    let mut key_columns = vec!["data_source_id".to_string()];
+    key_columns.extend(facets.iter().cloned());
+
+    let mut series: BTreeMap<Vec<String>, (Vec<Value>, BTreeSet<i64>)> = BTreeMap::new();
+
+    if facets.is_empty() {
+        for id in data_source_ids {
+            let values = vec![json!(id)];
+
+            series.insert(series_key(&values), (values, BTreeSet::new()));
+        }
+    }
+
+    let mut aggregates = BTreeSet::new();
+
+    for row in rows.iter_mut() {
+        let Some(start) = row.get(BUCKET_COLUMN).and_then(parse_timestamp) else {
+            continue;
+        };
+
+        let start = align(start, bucket);
+
+        row.insert(BUCKET_COLUMN.to_string(), json!(format_bucket(start)));
+
+        let values = key_columns
+            .iter()
+            .map(|column| row.get(column).cloned().unwrap_or(Value::Null))
+            .collect::<Vec<Value>>();
+
+        series
+            .entry(series_key(&values))
+            .or_insert_with(|| (values, BTreeSet::new()))
+            .1
+            .insert(start.timestamp());
+
+        aggregates.extend(
+            row.keys()
+                .filter(|column| *column != BUCKET_COLUMN && !key_columns.contains(column))
+                .cloned(),
+        );
+    }
+
+    let seen = series
+        .values()
+        .flat_map(|(_, starts)| starts.iter().copied());
+
+    let (Some(first), Some(last)) = (
+        query_input
+            .since
+            .map(|since| align(since.to_utc(), bucket).timestamp())
+            .or_else(|| seen.clone().min()),
+        query_input
+            .until
+            .map(|until| until.to_utc())
+            .or_else(|| query_input.since.map(|_| Utc::now()))
+            .map(|until| align(until, bucket).timestamp())
+            .or_else(|| seen.clone().max()),
+    ) else {
+        return;
+    };
+
+    let counts = query_input
+        .select
+        .iter()
+        .filter(|select| is_count(select))
+        .filter_map(Select::alias)
+        .collect::<BTreeSet<String>>();
+
+    let step = bucket.num_seconds();
+
+    if aggregates.is_empty() || (last - first) / step >= MAX_BUCKETS {
+        return;
+    }
+
+    for (values, starts) in series.values() {
+        for start in (first..=last).step_by(step as usize) {
+            if starts.contains(&start) {
+                continue;
+            }
+
+            let mut row = key_columns
+                .iter()
+                .cloned()
+                .zip(values.iter().cloned())
+                .filter(|(_, value)| !value.is_null())
+                .collect::<HashMap<String, Value>>();
+
+            row.extend(aggregates.iter().map(|column| {
+                let value = if counts.contains(column) {
+                    json!(0)
+                } else {
+                    Value::Null
+                };
+
+                (column.clone(), value)
+            }));
+
+            row.insert(
+                BUCKET_COLUMN.to_string(),
+                json!(format_bucket(DateTime::from_timestamp(start, 0).unwrap())),
+            );
+
+            rows.push(row);
+        }
+    }
+
+    rows.sort_by_cached_key(|row| {
+        std::iter::once(BUCKET_COLUMN)
+            .chain(key_columns.iter().map(String::as_str))
+            .map(|column| {
+                // Missing facet values are null in some rows and absent in others
+                row.get(column)
+                    .filter(|value| !value.is_null())
+                    .map(Value::to_string)
+                    .unwrap_or_default()
+            })
+            .collect::<Vec<String>>()
+    });
+}
+
+/// Aggregates that are 0 over no rows
+fn is_count(select: &Select) -> bool {
+    match select {
+        Select::Count(_) | Select::UniqueCount(_) => true,
+        Select::Rate(select, _) | Select::As(select, _) => is_count(select),
+        _ => false,
+    }
+}
+
+fn series_key(values: &[Value]) -> Vec<String> {
+    values.iter().map(Value::to_string).collect()
+}
+
+/// Start of the bucket `time` falls into, buckets are aligned to the epoch
+fn align(time: DateTime<Utc>, bucket: TimeDelta) -> DateTime<Utc> {
+    let step = bucket.num_seconds();
+
+    DateTime::from_timestamp(time.timestamp().div_euclid(step) * step, 0).unwrap_or(time)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, FixedOffset};
+
+    use crate::parsers::Facet;
+
+    use super::*;
+
+    fn time(input: &str) -> DateTime<FixedOffset> {
+        DateTime::parse_from_rfc3339(input).unwrap()
+    }
+
+    fn row(values: &[(&str, Value)]) -> HashMap<String, Value> {
+        values
+            .iter()
+            .map(|(column, value)| (column.to_string(), value.clone()))
+            .collect()
+    }
+
+    fn query_input(select: Vec<Select>, facet: &[&str]) -> QueryInput {
+        QueryInput {
+            select,
+            facet: facet
+                .iter()
+                .map(|column| Facet(column.to_string()))
+                .collect(),
+            since: Some(time("2024-01-01T00:00:00Z")),
+            until: Some(time("2024-01-01T00:15:00Z")),
+            timeseries: Some(TimeDelta::minutes(5)),
+            ..QueryInput::default()
+        }
+    }
+
+    fn ids(ids: &[&str]) -> Vec<String> {
+        ids.iter().map(|id| id.to_string()).collect()
+    }
+
+    /// (bucket, data source, count) of every row
+    fn counts(rows: &QueryResult) -> Vec<(String, String, Value)> {
+        rows.iter()
+            .map(|row| {
+                (
+                    row[BUCKET_COLUMN].as_str().unwrap()[11..16].to_string(),
+                    row["data_source_id"].as_str().unwrap().to_string(),
+                    row["count"].clone(),
+                )
+            })
+            .collect()
+    }
+
+    fn count(bucket: &str, id: &str, count: i64) -> (String, String, Value) {
+        (bucket.to_string(), id.to_string(), json!(count))
+    }
+
+    #[test]
+    fn fills_gaps_between_since_and_until() {
+        let mut rows = vec![row(&[
+            ("bucket", json!("2024-01-01T00:05:00.000Z")),
+            ("data_source_id", json!("alb")),
+            ("count", json!(3)),
+        ])];
+
+        zero_fill(
+            &mut rows,
+            &query_input(vec![Select::Count(None)], &[]),
+            &ids(&["alb"]),
+        );
+
+        assert_eq!(
+            counts(&rows),
+            vec![
+                count("00:00", "alb", 0),
+                count("00:05", "alb", 3),
+                count("00:10", "alb", 0),
+                count("00:15", "alb", 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn fills_every_data_source() {
+        let mut rows = vec![
+            row(&[
+                ("bucket", json!("2024-01-01 00:10:00.000 UTC")),
+                ("data_source_id", json!("alb")),
+                ("count", json!(2)),
+            ]),
+            row(&[
+                ("bucket", json!(1_704_067_200_000i64)),
+                ("data_source_id", json!("nr")),
+                ("count", json!(1)),
+            ]),
+        ];
+
+        let mut input = query_input(vec![Select::Count(None)], &[]);
+        input.until = Some(time("2024-01-01T00:10:00Z"));
+
+        zero_fill(&mut rows, &input, &ids(&["alb", "nr", "failed"]));
+
+        assert_eq!(
+            counts(&rows),
+            vec![
+                count("00:00", "alb", 0),
+                count("00:00", "failed", 0),
+                count("00:00", "nr", 1),
+                count("00:05", "alb", 0),
+                count("00:05", "failed", 0),
+                count("00:05", "nr", 0),
+                count("00:10", "alb", 2),
+                count("00:10", "failed", 0),
+                count("00:10", "nr", 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn fills_every_facet_including_missing_values() {
+        let mut rows = vec![
+            row(&[
+                ("bucket", json!("2024-01-01T00:00:00.000Z")),
+                ("data_source_id", json!("alb")),
+                ("code", json!(500)),
+                ("count", json!(1)),
+            ]),
+            row(&[
+                ("bucket", json!("2024-01-01T00:05:00.000Z")),
+                ("data_source_id", json!("alb")),
+                ("code", Value::Null),
+                ("count", json!(4)),
+            ]),
+        ];
+
+        let mut input = query_input(vec![Select::Count(None)], &["code"]);
+        input.until = Some(time("2024-01-01T00:05:00Z"));
+
+        zero_fill(&mut rows, &input, &ids(&["alb"]));
+
+        let series = rows
+            .iter()
+            .map(|row| {
+                (
+                    row[BUCKET_COLUMN].as_str().unwrap()[11..16].to_string(),
+                    row.get("code").cloned(),
+                    row["count"].clone(),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            series,
+            vec![
+                ("00:00".to_string(), None, json!(0)),
+                ("00:00".to_string(), Some(json!(500)), json!(1)),
+                ("00:05".to_string(), Some(Value::Null), json!(4)),
+                ("00:05".to_string(), Some(json!(500)), json!(0)),
+            ]
+        );
+    }
+
+    #[test]
+    fn only_counts_are_zero() {
+        let mut rows = vec![row(&[
+            ("bucket", json!("2024-01-01T00:00:00.000Z")),
+            ("data_source_id", json!("alb")),
+            ("count", json!(2)),
+            ("hits", json!(1)),
+            ("max_target_processing_time", json!(0.5)),
+        ])];
+
+        let select = vec![
+            Select::Count(None),
+            Select::As(Box::new(Select::Count(None)), "hits".to_string()),
+            Select::Max("target_processing_time".to_string()),
+        ];
+
+        let mut input = query_input(select, &[]);
+        input.until = Some(time("2024-01-01T00:05:00Z"));
+
+        zero_fill(&mut rows, &input, &ids(&["alb"]));
+
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[1]["count"], json!(0));
+        assert_eq!(rows[1]["hits"], json!(0));
+        assert_eq!(rows[1]["max_target_processing_time"], Value::Null);
+    }
+
+    #[test]
+    fn aligns_buckets_to_the_epoch() {
+        let mut rows = vec![row(&[
+            ("bucket", json!("2024-01-01T00:10:00.000Z")),
+            ("data_source_id", json!("alb")),
+            ("count", json!(1)),
+        ])];
+
+        let input = QueryInput {
+            select: vec![Select::Count(None)],
+            timeseries: Some(TimeDelta::minutes(7)),
+            ..QueryInput::default()
+        };
+
+        zero_fill(&mut rows, &input, &ids(&["alb"]));
+
+        // 1704067800 s rounded down to a multiple of 420 s
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0][BUCKET_COLUMN], json!("2024-01-01T00:08:00.000Z"));
+    }
+
+    #[test]
+    fn fills_up_to_now_without_until() {
+        let now = Utc::now();
+        let mut input = query_input(vec![Select::Count(None)], &[]);
+
+        input.since = Some((now - TimeDelta::minutes(20)).fixed_offset());
+        input.until = None;
+
+        let mut rows = vec![row(&[
+            ("bucket", json!(format_bucket(now - TimeDelta::minutes(20)))),
+            ("data_source_id", json!("alb")),
+            ("count", json!(1)),
+        ])];
+
+        zero_fill(&mut rows, &input, &ids(&["alb"]));
+
+        // A bucket may have started since `now`
+        let last = rows.last().unwrap()[BUCKET_COLUMN].clone();
+        let latest = align(Utc::now(), TimeDelta::minutes(5));
+
+        assert!(rows.len() == 5 || rows.len() == 6);
+        assert!(
+            last == json!(format_bucket(align(now, TimeDelta::minutes(5))))
+                || last == json!(format_bucket(latest))
+        );
+    }
+
+    #[test]
+    fn skips_filling_too_many_buckets() {
+        let mut rows = vec![row(&[
+            ("bucket", json!("2024-01-01T00:00:00.000Z")),
+            ("data_source_id", json!("alb")),
+            ("count", json!(1)),
+        ])];
+
+        let mut input = query_input(vec![Select::Count(None)], &[]);
+        input.until = Some(time("2024-01-02T00:00:00Z"));
+        input.timeseries = Some(TimeDelta::seconds(1));
+
+        zero_fill(&mut rows, &input, &ids(&["alb"]));
+
+        assert_eq!(rows.len(), 1);
+    }
+}